@@ -0,0 +1,104 @@
+//! Compares the throughput of GZIP compression levels on a vector tile resembling real-world road data, to help
+//! choosing between fast live updates and small archive builds.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use rosm_mbtiles::common::gzip_with_level;
+
+const LEVELS: [u32; 4] = [1, 3, 6, 9];
+
+/// Appends the given value as a protocol buffers varint.
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Appends the given length-delimited field.
+fn bytes_field(buf: &mut Vec<u8>, field: u64, data: &[u8]) {
+    varint(buf, field << 3 | 2);
+    varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Encodes a vector tile with a single layer of line features with a few attributes, with deterministic
+/// pseudo-random geometries.
+fn road_tile(feature_count: u64) -> Vec<u8> {
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = move |bound: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % bound
+    };
+
+    let mut layer = Vec::new();
+    varint(&mut layer, 15 << 3);
+    varint(&mut layer, 2);
+    bytes_field(&mut layer, 1, b"transportation");
+
+    for id in 0..feature_count {
+        let mut feature = Vec::new();
+        varint(&mut feature, 1 << 3);
+        varint(&mut feature, id);
+
+        let mut tags = Vec::new();
+        for (key, value_count) in [(0, 8), (1, 40), (2, 4)].iter() {
+            varint(&mut tags, *key);
+            varint(&mut tags, key * 100 + random(*value_count));
+        }
+        bytes_field(&mut feature, 2, &tags);
+
+        varint(&mut feature, 3 << 3);
+        varint(&mut feature, 2);
+
+        let vertex_count = 4 + random(28);
+        let mut geometry = Vec::new();
+        varint(&mut geometry, 1 << 3 | 1);
+        varint(&mut geometry, random(8192));
+        varint(&mut geometry, random(8192));
+        varint(&mut geometry, (vertex_count - 1) << 3 | 2);
+        for _ in 1..vertex_count {
+            varint(&mut geometry, random(64));
+            varint(&mut geometry, random(64));
+        }
+        bytes_field(&mut feature, 4, &geometry);
+
+        bytes_field(&mut layer, 2, &feature);
+    }
+
+    for key in ["class", "name", "oneway"].iter() {
+        bytes_field(&mut layer, 3, key.as_bytes());
+    }
+    for i in 0..300u32 {
+        bytes_field(&mut layer, 4, &{
+            let mut value = Vec::new();
+            bytes_field(&mut value, 1, format!("value {}", i).as_bytes());
+            value
+        });
+    }
+    varint(&mut layer, 5 << 3);
+    varint(&mut layer, 4096);
+
+    let mut tile = Vec::new();
+    bytes_field(&mut tile, 3, &layer);
+    tile
+}
+
+fn gzip_levels(c: &mut Criterion) {
+    let tile = road_tile(2000);
+
+    let mut group = c.benchmark_group("gzip_levels");
+    group.throughput(Throughput::Bytes(tile.len() as u64));
+    for &level in LEVELS.iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(level), &level, |b, &level| {
+            b.iter(|| gzip_with_level(&tile, level))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gzip_levels);
+criterion_main!(benches);
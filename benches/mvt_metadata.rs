@@ -0,0 +1,83 @@
+//! Compares reading the whole vector tile metadata with `read_mvt_metadata` against reading only the vector layers
+//! with `read_mvt_metadata_lazy`, on a `json` row with a large `tilestats` object.
+//!
+//! The `json` row is read from the file given by the `MBTILES_BENCH_JSON` environment variable, e.g. exported from a
+//! real-world tileset with `sqlite3 tiles.mbtiles "SELECT value FROM metadata WHERE name = 'json'"`. Without it, a
+//! row resembling the output of tippecanoe for a dataset with many attributes is generated.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use serde_json::json;
+
+use rosm_mbtiles::read::{read_mvt_metadata, read_mvt_metadata_lazy};
+use rosm_mbtiles::write::{create_metadata_table, set_metadata_value};
+
+fn generated_json() -> String {
+    const LAYERS: usize = 20;
+    const ATTRIBUTES: usize = 50;
+    const VALUES: usize = 100;
+
+    let vector_layers: Vec<_> = (0..LAYERS)
+        .map(|layer| {
+            let fields: serde_json::Map<_, _> = (0..ATTRIBUTES)
+                .map(|attribute| (format!("attribute_{}", attribute), json!("String")))
+                .collect();
+            json!({ "id": format!("layer_{}", layer), "fields": fields, "minzoom": 0, "maxzoom": 14 })
+        })
+        .collect();
+
+    let layers: Vec<_> = (0..LAYERS)
+        .map(|layer| {
+            let attributes: Vec<_> = (0..ATTRIBUTES)
+                .map(|attribute| {
+                    let values: Vec<_> = (0..VALUES)
+                        .map(|value| format!("value {} {}", attribute, value))
+                        .collect();
+                    json!({
+                        "attribute": format!("attribute_{}", attribute),
+                        "count": VALUES,
+                        "type": "string",
+                        "values": values,
+                    })
+                })
+                .collect();
+            json!({
+                "layer": format!("layer_{}", layer),
+                "count": 100_000,
+                "geometry": "Polygon",
+                "attributeCount": ATTRIBUTES,
+                "attributes": attributes,
+            })
+        })
+        .collect();
+
+    json!({
+        "vector_layers": vector_layers,
+        "tilestats": { "layerCount": LAYERS, "layers": layers },
+    })
+    .to_string()
+}
+
+fn mvt_metadata(c: &mut Criterion) {
+    let json = match std::env::var_os("MBTILES_BENCH_JSON") {
+        Some(path) => std::fs::read_to_string(path).expect("Expected a readable json file"),
+        None => generated_json(),
+    };
+
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    let tr = conn.transaction().unwrap();
+    create_metadata_table(&tr).unwrap();
+    set_metadata_value(&tr, "json", &json).unwrap();
+    tr.commit().unwrap();
+
+    let mut group = c.benchmark_group("mvt_metadata");
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    group.bench_function("read_mvt_metadata", |b| b.iter(|| read_mvt_metadata(&conn).unwrap()));
+    group.bench_function("read_mvt_metadata_lazy", |b| {
+        b.iter(|| read_mvt_metadata_lazy(&conn).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mvt_metadata);
+criterion_main!(benches);
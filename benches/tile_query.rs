@@ -0,0 +1,51 @@
+//! Compares looking up tiles with `read_tile`, which goes through the statement cache on every call, against a
+//! `TileQuery` holding an already prepared statement.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use rosm_mbtiles::common::tile_id_from_row;
+use rosm_mbtiles::read::{read_tile, TileQuery};
+use rosm_mbtiles::write::{create_tile_index, create_tiles_table, write_tile};
+
+const ZOOM: u32 = 6;
+
+fn tile_lookup(c: &mut Criterion) {
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    let tr = conn.transaction().unwrap();
+    create_tiles_table(&tr).unwrap();
+    create_tile_index(&tr).unwrap();
+    for x in 0..1 << ZOOM {
+        for y in 0..1 << ZOOM {
+            write_tile(&tr, tile_id_from_row(ZOOM, x, y).unwrap(), vec![0; 256]).unwrap();
+        }
+    }
+    tr.commit().unwrap();
+
+    let tile_ids: Vec<_> = (0..1 << ZOOM)
+        .map(|i| tile_id_from_row(ZOOM, i, (i * 7) % (1 << ZOOM)).unwrap())
+        .collect();
+
+    let mut group = c.benchmark_group("tile_lookup");
+
+    group.bench_function("read_tile", |b| {
+        b.iter(|| {
+            for &tile_id in &tile_ids {
+                read_tile(&conn, tile_id).unwrap();
+            }
+        })
+    });
+
+    let mut query = TileQuery::new(&conn).unwrap();
+    group.bench_function("tile_query", |b| {
+        b.iter(|| {
+            for &tile_id in &tile_ids {
+                query.get(tile_id).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, tile_lookup);
+criterion_main!(benches);
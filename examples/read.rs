@@ -7,13 +7,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .nth(1)
         .expect("Expected path to an MBTiles database as first argument");
 
-    let conn = rusqlite::Connection::open(&mbtiles)?;
-
-    let metadata = read_metadata(&conn)?;
+    let metadata = metadata_of(&mbtiles)?;
 
     println!("{:?}", metadata);
 
-    let tile_id = TileId::new(1, 2, 3)?;
+    let conn = open_read_only(&mbtiles)?;
+
+    let tile_id = TileId::new(3, 2, 1)?;
 
     if let Ok(Some(tile_data)) = read_tile(&conn, tile_id.into()) {
         println!("Found tile {:?}, data length: {}", tile_id, tile_data.len());
@@ -4,18 +4,10 @@ use rosm_mbtiles::common::*;
 use rosm_mbtiles::write::*;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut conn = rusqlite::Connection::open(&"example.mbtiles")?;
+    let mut conn = create_mbtiles("example.mbtiles", false)?;
 
     let tr = conn.transaction()?;
 
-    set_application_id(&tr)?;
-
-    create_metadata_table(&tr)?;
-    create_tiles_table(&tr)?;
-    // Optionally: create_grid_tables(&tr)?;
-
-    create_tile_index(&tr)?;
-
     let mvt_metadata = MvtMetadata {
         vector_layers: Vec::new(),
         tilestats: None,
@@ -27,9 +19,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ..Default::default()
     };
 
-    write_metadata(&tr, metadata)?;
+    write_metadata(&tr, &metadata)?;
 
-    let tile_id = TileId::new(1, 2, 3)?;
+    let tile_id = TileId::new(3, 2, 1)?;
     let tile_data = Vec::new(); // Gzip-compressed MVT PBF
     write_tile(&tr, tile_id.into(), tile_data)?;
 
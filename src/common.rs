@@ -1,18 +1,28 @@
 //! Common types for reading/writing MBTiles databases.
 
 use rosm_geo::coord::GeoCoord;
+use rosm_geo::mercator::{TileId, TmsTileId};
 use rosm_geo::rect::GeoRect;
 
 use rosm_geostats::Tilestats;
 
+#[cfg(feature = "compression")]
+use flate2::{read::GzDecoder, write::GzEncoder};
+
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use std::convert::{Into, TryFrom};
+use std::f64::consts::PI;
+use std::fmt;
+#[cfg(feature = "compression")]
+use std::io::{Read, Write};
 use std::ops::RangeInclusive;
+use std::str::FromStr;
 
 /// File format of the tile data.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileFormat {
     /// GZIP-compressed [Mapbox Vector Tiles](https://github.com/mapbox/vector-tile-spec).
     Pbf(MvtMetadata),
@@ -23,32 +33,281 @@ pub enum FileFormat {
     Other(String),
 }
 
+impl FileFormat {
+    /// Creates an [`Other`](FileFormat::Other) format from the given IETF media type, validating its `type/subtype`
+    /// shape and top-level type.
+    pub fn other(media_type: &str) -> Result<FileFormat, InvalidMediaType> {
+        const TOP_LEVEL_TYPES: &[&str] = &[
+            "application",
+            "audio",
+            "example",
+            "font",
+            "image",
+            "message",
+            "model",
+            "multipart",
+            "text",
+            "video",
+        ];
+
+        // See https://tools.ietf.org/html/rfc6838#section-4.2
+        fn is_restricted_name(name: &str) -> bool {
+            let mut chars = name.chars();
+            let first_is_alphanumeric = matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric());
+            first_is_alphanumeric
+                && name.len() <= 127
+                && chars.all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+        }
+
+        let mut split = media_type.splitn(2, '/');
+        let (top_level_type, subtype) = match (split.next(), split.next()) {
+            (Some(top_level_type), Some(subtype)) => (top_level_type, subtype),
+            _ => return Err(InvalidMediaType(media_type.to_owned())),
+        };
+
+        let top_level_type_is_valid = TOP_LEVEL_TYPES.contains(&top_level_type.to_ascii_lowercase().as_str());
+
+        if top_level_type_is_valid && is_restricted_name(subtype) {
+            Ok(FileFormat::Other(media_type.to_owned()))
+        } else {
+            Err(InvalidMediaType(media_type.to_owned()))
+        }
+    }
+
+    /// Returns the value of the `format` metadata row for this format.
+    pub fn as_str(&self) -> &str {
+        match self {
+            FileFormat::Pbf(_) => "pbf",
+            FileFormat::Jpg => "jpg",
+            FileFormat::Png => "png",
+            FileFormat::Webp => "webp",
+            FileFormat::Other(ietf_type) => ietf_type,
+        }
+    }
+}
+
 impl Into<String> for FileFormat {
     fn into(self) -> String {
         match self {
-            FileFormat::Pbf(_) => "pbf".to_owned(),
-            FileFormat::Jpg => "jpg".to_owned(),
-            FileFormat::Png => "png".to_owned(),
-            FileFormat::Webp => "webp".to_owned(),
             FileFormat::Other(ietf_type) => ietf_type,
+            format => format.as_str().to_owned(),
         }
     }
 }
 
+/// Error for strings which are not valid IETF media types.
+#[derive(Debug)]
+pub struct InvalidMediaType(pub String);
+
+impl fmt::Display for InvalidMediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid media type {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidMediaType {}
+
 impl Default for FileFormat {
     fn default() -> Self {
         FileFormat::Other(String::new())
     }
 }
 
-#[derive(Debug)]
+/// Compression applied to the tile data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    /// [Brotli](https://github.com/google/brotli) compression, which can only be detected heuristically.
+    Brotli,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl Compression {
+    /// Returns the value of the `compression` metadata row for this compression.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Brotli => "brotli",
+        }
+    }
+}
+
+impl TryFrom<&str> for Compression {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Guesses the compression of the given tile data from its leading magic bytes.
+///
+/// GZIP and Zstandard streams are recognized by their magic numbers. Brotli streams have no magic number, so any data
+/// which is neither one of those nor a recognized uncompressed format (PNG, JPEG, WebP, GIF or a Mapbox Vector Tile
+/// starting with a layer) is assumed to be Brotli-compressed.
+pub fn detect_compression(data: &[u8]) -> Compression {
+    recognize_compression(data).unwrap_or(Compression::Brotli)
+}
+
+/// Recognizes the compression of the given tile data from its leading magic bytes like [`detect_compression`], but
+/// returns `None` instead of assuming Brotli for unrecognized data.
+pub(crate) fn recognize_compression(data: &[u8]) -> Option<Compression> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        Some(Compression::Gzip)
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(Compression::Zstd)
+    } else if data.is_empty() || is_uncompressed(data) {
+        Some(Compression::None)
+    } else {
+        None
+    }
+}
+
+/// Guesses the media type of the given tile data from its leading magic bytes, e.g. for a `Content-Type` header.
+///
+/// GZIP-compressed data and data starting with a Mapbox Vector Tile layer is assumed to be a vector tile. Unrecognized
+/// data results in `application/octet-stream`.
+pub fn detect_media_type(data: &[u8]) -> &'static str {
+    if data.starts_with(&[0x89, b'P', b'N', b'G']) {
+        "image/png"
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.starts_with(&[0x1f, 0x8b]) || data.first() == Some(&0x1a) {
+        "application/x-protobuf"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Details of a raster tile read from its image header, see [`image_details`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDetails {
+    /// The media type of the image, e.g. `image/png`.
+    pub media_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// The number of bits per sample (PNG) or component (JPEG), unknown for WebP.
+    pub bit_depth: Option<u8>,
+    /// The [PNG color type](https://www.w3.org/TR/png/#6Colour-values), e.g. 3 for indexed color, or the number of
+    /// color components for JPEG. Unknown for WebP.
+    pub color_type: Option<u8>,
+}
+
+impl ImageDetails {
+    /// Checks whether the image is an indexed-color PNG (often called PNG8).
+    pub fn is_indexed(&self) -> bool {
+        self.media_type == "image/png" && self.color_type == Some(3)
+    }
+}
+
+/// Reads the details of the given PNG, JPEG or WebP image from its header, without decoding the image.
+///
+/// Returns `None` for other data or truncated headers.
+pub fn image_details(data: &[u8]) -> Option<ImageDetails> {
+    let be_u16 = |offset: usize| Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]));
+    let le_u24 = |offset: usize| {
+        let bytes = data.get(offset..offset + 3)?;
+        Some(u32::from(bytes[0]) | (u32::from(bytes[1]) << 8) | (u32::from(bytes[2]) << 16))
+    };
+
+    if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+        // The IHDR chunk is required to come first
+        let ihdr = data.get(12..26).filter(|ihdr| ihdr.starts_with(b"IHDR"))?;
+        return Some(ImageDetails {
+            media_type: "image/png",
+            width: u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]),
+            height: u32::from_be_bytes([ihdr[8], ihdr[9], ihdr[10], ihdr[11]]),
+            bit_depth: Some(ihdr[12]),
+            color_type: Some(ihdr[13]),
+        });
+    }
+
+    if data.starts_with(&[0xff, 0xd8]) {
+        // Skip segments until a start of frame marker, which isn't DHT, JPG or DAC
+        let mut offset = 2;
+        while *data.get(offset)? == 0xff {
+            let marker = *data.get(offset + 1)?;
+            if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+                return Some(ImageDetails {
+                    media_type: "image/jpeg",
+                    width: u32::from(be_u16(offset + 7)?),
+                    height: u32::from(be_u16(offset + 5)?),
+                    bit_depth: Some(*data.get(offset + 4)?),
+                    color_type: Some(*data.get(offset + 9)?),
+                });
+            }
+            offset += 2 + usize::from(be_u16(offset + 2)?);
+        }
+        return None;
+    }
+
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        let (width, height) = match data.get(12..16)? {
+            b"VP8 " => (
+                u32::from(u16::from_le_bytes([*data.get(26)?, *data.get(27)?]) & 0x3fff),
+                u32::from(u16::from_le_bytes([*data.get(28)?, *data.get(29)?]) & 0x3fff),
+            ),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes([*data.get(21)?, *data.get(22)?, *data.get(23)?, *data.get(24)?]);
+                ((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1)
+            }
+            b"VP8X" => (le_u24(24)? + 1, le_u24(27)? + 1),
+            _ => return None,
+        };
+        return Some(ImageDetails {
+            media_type: "image/webp",
+            width,
+            height,
+            bit_depth: None,
+            color_type: None,
+        });
+    }
+
+    None
+}
+
+fn is_uncompressed(data: &[u8]) -> bool {
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G'];
+    const JPEG: &[u8] = &[0xff, 0xd8, 0xff];
+    const GIF: &[u8] = b"GIF8";
+    // Field 3 (`layers`) of a `Tile` message, with the length-delimited wire type.
+    const MVT_LAYER_TAG: u8 = 0x1a;
+
+    let webp = data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP";
+
+    data.starts_with(PNG) || data.starts_with(JPEG) || data.starts_with(GIF) || webp || data[0] == MVT_LAYER_TAG
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Type {
     Overlay,
     BaseLayer,
 }
 
-impl Into<&'static str> for Type {
-    fn into(self) -> &'static str {
+impl Type {
+    /// Returns the value of the `type` metadata row for this type.
+    pub fn as_str(&self) -> &'static str {
         match self {
             Type::Overlay => "overlay",
             Type::BaseLayer => "baselayer",
@@ -56,6 +315,12 @@ impl Into<&'static str> for Type {
     }
 }
 
+impl Into<&'static str> for Type {
+    fn into(self) -> &'static str {
+        self.as_str()
+    }
+}
+
 impl TryFrom<&str> for Type {
     type Error = ();
 
@@ -68,30 +333,621 @@ impl TryFrom<&str> for Type {
     }
 }
 
+impl TryFrom<String> for Type {
+    type Error = ();
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Type::try_from(value.as_str())
+    }
+}
+
+impl FromStr for Type {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Type::try_from(s)
+    }
+}
+
+impl AsRef<str> for Type {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The tile addressing scheme of a tileset, as declared by the nonstandard `scheme` metadata row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// Rows counted from the south, as required by the MBTiles specification.
+    Tms,
+    /// Rows counted from the north, as used by most web maps.
+    Xyz,
+}
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Scheme::Tms
+    }
+}
+
+impl Scheme {
+    /// Returns the value of the `scheme` metadata row for this scheme.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Tms => "tms",
+            Scheme::Xyz => "xyz",
+        }
+    }
+}
+
+impl TryFrom<&str> for Scheme {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "tms" => Ok(Scheme::Tms),
+            "xyz" => Ok(Scheme::Xyz),
+            _ => Err(()),
+        }
+    }
+}
+
 /// A key/value store for settings.
-#[derive(Debug, Default)]
+///
+/// When (de)serialized with serde, `bounds` and `antimeridian_bounds` are represented as `[west, south, east, north]`
+/// arrays and `center` as a `[longitude, latitude, zoom]` array.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Metadata {
     /// The human-readable name of the tileset.
     pub name: String,
     /// The file format of the tile data.
     pub format: FileFormat,
     /// The maximum extent of the rendered map area.
+    #[serde(skip_serializing_if = "Option::is_none", with = "metadata_serde::bounds")]
     pub bounds: Option<GeoRect>,
+    /// The extent of the map if it crosses the antimeridian (e.g. `177,-20,-178,-12`), split into the parts west and
+    /// east of it, in which case `bounds` is `None`.
+    ///
+    /// When writing, `bounds` takes precedence if both are present.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "metadata_serde::antimeridian_bounds"
+    )]
+    pub antimeridian_bounds: Option<(GeoRect, GeoRect)>,
     /// The longitude, latitude, and zoom level of the default view of the map.
+    #[serde(skip_serializing_if = "Option::is_none", with = "metadata_serde::center")]
     pub center: Option<(GeoCoord, u32)>,
-    /// The lowest and highest zoom levels for which the tileset provides data.
-    pub zoom_range: Option<RangeInclusive<u32>>,
+    /// The lowest zoom level for which the tileset provides data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minzoom: Option<u32>,
+    /// The highest zoom level for which the tileset provides data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxzoom: Option<u32>,
     /// An attribution string, which explains the sources of data and/or style for the map.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub attribution: Option<String>,
     /// A description of the tileset's content.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub r#type: Option<Type>,
     /// The version of the tileset. This refers to a revision of the tileset itself, not of the MBTiles specification.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<u32>,
+    /// The tool which produced the tileset, e.g. `tippecanoe v2.0.0`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+    /// The options the tileset was produced with by the [`generator`](Self::generator).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator_options: Option<String>,
+    /// The compression of the tile data stored in the `compression` row, e.g. for setting the `Content-Encoding` when
+    /// serving tiles.
+    ///
+    /// See [`effective_compression`](Self::effective_compression) for databases without a `compression` row.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// The compression inferred from the magic bytes of the first tile when reading a database without a
+    /// `compression` row.
+    ///
+    /// Only compressions recognized with certainty are inferred, so unrecognized data is never assumed to be
+    /// Brotli-compressed. This is never written, as it's a guess rather than a stored value.
+    #[serde(skip)]
+    pub inferred_compression: Option<Compression>,
+    /// The tile addressing scheme of the tileset, which is [`Scheme::Tms`] if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<Scheme>,
+    /// The raw `json` row, e.g. UTFGrid interactivity settings of raster tilesets.
+    ///
+    /// **Note:** for `pbf` tilesets the `json` row is also parsed into [`FileFormat::Pbf`], which is written instead of
+    /// this row.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_json: Option<String>,
+    /// The well-known fields of the `json` row of raster tilesets, if any of them is present.
+    ///
+    /// When writing, these fields are merged over the object in [`raw_json`](Self::raw_json).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raster_json: Option<RasterJson>,
     /// Additional rows stored for other purposes.
+    ///
+    /// When reading, the entries of a `custom_json` row written by
+    /// [`write_metadata_with_custom_json`](crate::write::write_metadata_with_custom_json) are included, with separate
+    /// rows taking precedence. A `custom_json` row that isn't such an object is included as is.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
     pub custom: HashMap<String, String>,
 }
 
+impl Metadata {
+    /// The `bounds` to assume when the row is absent, as given by the MBTiles specification.
+    pub const DEFAULT_BOUNDS: [f64; 4] = [-180.0, -85.0511, 180.0, 85.0511];
+
+    /// Returns the `bounds`, or the [default bounds](Self::DEFAULT_BOUNDS) of the whole world if they're absent.
+    ///
+    /// Bounds crossing the antimeridian are widened to all longitudes.
+    pub fn effective_bounds(&self) -> GeoRect {
+        if let Some(bounds) = &self.bounds {
+            return bounds.clone();
+        }
+
+        let [west, mut south, east, mut north] = Self::DEFAULT_BOUNDS;
+        if let Some((west_part, _)) = &self.antimeridian_bounds {
+            south = west_part.bottom_right().lat();
+            north = west_part.top_left().lat();
+        }
+
+        let tl = GeoCoord::from_degrees(west, north).expect("bounds are valid");
+        let br = GeoCoord::from_degrees(east, south).expect("bounds are valid");
+        GeoRect::new(tl, br).expect("bounds are valid")
+    }
+
+    /// Returns the zoom levels for which the tileset provides data, if both `minzoom` and `maxzoom` are present.
+    pub fn zoom_range(&self) -> Option<RangeInclusive<u32>> {
+        match (self.minzoom, self.maxzoom) {
+            (Some(minzoom), Some(maxzoom)) => Some(minzoom..=maxzoom),
+            _ => None,
+        }
+    }
+
+    /// Returns the `bounds` as a `[west, south, east, north]` array in degrees, like the `bounds` row stores them.
+    ///
+    /// For bounds crossing the antimeridian, `west` is greater than `east`.
+    pub fn bounds_array(&self) -> Option<[f64; 4]> {
+        let (tl, br) = match (&self.bounds, &self.antimeridian_bounds) {
+            (Some(bounds), _) => (bounds.top_left(), bounds.bottom_right()),
+            (None, Some((west, east))) => (west.top_left(), east.bottom_right()),
+            (None, None) => return None,
+        };
+        Some([tl.lon(), br.lat(), br.lon(), tl.lat()])
+    }
+
+    /// Returns the `center`, or if it's absent, the center of the `bounds` at a zoom level at which the bounds roughly
+    /// fit into a single tile, clamped into the zoom levels of the tileset.
+    ///
+    /// The center of bounds crossing the antimeridian is the center of both parts together. If both the `center` and
+    /// the `bounds` are absent, `None` is returned.
+    pub fn effective_center(&self) -> Option<(GeoCoord, u32)> {
+        if self.center.is_some() {
+            return self.center.clone();
+        }
+
+        let [west, south, east, north] = self.bounds_array()?;
+        let lon_span = if west > east { east - west + 360.0 } else { east - west };
+
+        let width = lon_span.max(north - south);
+        let mut zoom = if width > 0.0 {
+            (360.0 / width).log2().floor().max(0.0).min(MAX_ZOOM_LEVEL as f64) as u32
+        } else {
+            MAX_ZOOM_LEVEL
+        };
+        if let Some(minzoom) = self.minzoom {
+            zoom = zoom.max(minzoom);
+        }
+        if let Some(maxzoom) = self.maxzoom {
+            zoom = zoom.min(maxzoom);
+        }
+
+        let mut lon = west + lon_span / 2.0;
+        if lon > 180.0 {
+            lon -= 360.0;
+        }
+        let coord = GeoCoord::from_degrees(lon, (north + south) / 2.0).ok()?;
+        Some((coord, zoom))
+    }
+
+    /// Returns the value of the `format` metadata row, e.g. `pbf` or `png`, see [`FileFormat::as_str`].
+    pub fn format_name(&self) -> &str {
+        self.format.as_str()
+    }
+
+    /// Returns the IDs of the vector layers of `pbf` tilesets, or an empty list for other formats.
+    pub fn layer_ids(&self) -> Vec<&str> {
+        match &self.format {
+            FileFormat::Pbf(mvt_metadata) => mvt_metadata
+                .vector_layers
+                .iter()
+                .map(|layer| layer.id.as_str())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Builds a [TileJSON](https://github.com/mapbox/tilejson-spec) 3.0.0 document describing the tileset, served from
+    /// the given tile URL template, e.g. `https://example.com/{z}/{x}/{y}.pbf`.
+    ///
+    /// The `version` is written as the major version of a semantic version, and the vector layers of `pbf` tilesets
+    /// are included. Bounds crossing the antimeridian are written with `west` greater than `east`, like in the
+    /// `bounds` row.
+    pub fn to_tilejson(&self, tile_url_template: &str) -> serde_json::Result<serde_json::Value> {
+        let mut tilejson = serde_json::Map::new();
+
+        tilejson.insert("tilejson".to_owned(), "3.0.0".into());
+        tilejson.insert("tiles".to_owned(), vec![tile_url_template].into());
+
+        if !self.name.is_empty() {
+            tilejson.insert("name".to_owned(), self.name.as_str().into());
+        }
+        if let Some(description) = &self.description {
+            tilejson.insert("description".to_owned(), description.as_str().into());
+        }
+        if let Some(version) = self.version {
+            tilejson.insert("version".to_owned(), format!("{}.0.0", version).into());
+        }
+        if let Some(attribution) = &self.attribution {
+            tilejson.insert("attribution".to_owned(), attribution.as_str().into());
+        }
+        if let Some(minzoom) = self.minzoom {
+            tilejson.insert("minzoom".to_owned(), minzoom.into());
+        }
+        if let Some(maxzoom) = self.maxzoom {
+            tilejson.insert("maxzoom".to_owned(), maxzoom.into());
+        }
+        if let Some(bounds) = self.bounds_array() {
+            tilejson.insert("bounds".to_owned(), bounds.to_vec().into());
+        }
+        if let Some((coord, zoom)) = &self.center {
+            tilejson.insert(
+                "center".to_owned(),
+                vec![coord.lon().into(), coord.lat().into(), serde_json::Value::from(*zoom)].into(),
+            );
+        }
+        if let FileFormat::Pbf(mvt_metadata) = &self.format {
+            tilejson.insert(
+                "vector_layers".to_owned(),
+                serde_json::to_value(&mvt_metadata.vector_layers)?,
+            );
+        }
+
+        Ok(tilejson.into())
+    }
+
+    /// Merges the metadata with the metadata of another tileset, e.g. when combining extracts of the same tileset.
+    ///
+    /// The zoom ranges and bounds are unioned, the latter into the enclosing rectangle, and the `custom` rows are
+    /// merged. For the `name` and `description` the non-empty value is preferred. For other conflicting fields,
+    /// including the `format` (and with it the vector layers), the values of `self` take precedence over the ones of
+    /// `other`.
+    pub fn merged_with(&self, other: &Metadata) -> Metadata {
+        let bounds = match (&self.bounds, &other.bounds) {
+            (Some(a), Some(b)) => {
+                let west = a.top_left().lon().min(b.top_left().lon());
+                let north = a.top_left().lat().max(b.top_left().lat());
+                let east = a.bottom_right().lon().max(b.bottom_right().lon());
+                let south = a.bottom_right().lat().min(b.bottom_right().lat());
+                let tl = GeoCoord::from_degrees(west, north).expect("union of valid bounds is valid");
+                let br = GeoCoord::from_degrees(east, south).expect("union of valid bounds is valid");
+                Some(GeoRect::new(tl, br).expect("union of valid bounds is valid"))
+            }
+            (bounds, other_bounds) => bounds.as_ref().or_else(|| other_bounds.as_ref()).cloned(),
+        };
+
+        let union = |a: Option<u32>, b: Option<u32>, f: fn(u32, u32) -> u32| match (a, b) {
+            (Some(a), Some(b)) => Some(f(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        let description = self.description.as_ref().filter(|description| !description.is_empty());
+
+        let mut custom = other.custom.clone();
+        custom.extend(self.custom.iter().map(|(key, value)| (key.clone(), value.clone())));
+
+        Metadata {
+            name: if self.name.is_empty() { &other.name } else { &self.name }.clone(),
+            format: self.format.clone(),
+            bounds,
+            antimeridian_bounds: self
+                .antimeridian_bounds
+                .clone()
+                .or_else(|| other.antimeridian_bounds.clone()),
+            center: self.center.clone().or_else(|| other.center.clone()),
+            minzoom: union(self.minzoom, other.minzoom, u32::min),
+            maxzoom: union(self.maxzoom, other.maxzoom, u32::max),
+            attribution: self.attribution.clone().or_else(|| other.attribution.clone()),
+            description: description.or_else(|| other.description.as_ref()).cloned(),
+            r#type: self.r#type.or(other.r#type),
+            version: self.version.or(other.version),
+            generator: self.generator.clone().or_else(|| other.generator.clone()),
+            generator_options: self
+                .generator_options
+                .clone()
+                .or_else(|| other.generator_options.clone()),
+            compression: self.compression.or(other.compression),
+            inferred_compression: self.inferred_compression.or(other.inferred_compression),
+            scheme: self.scheme.or(other.scheme),
+            raw_json: self.raw_json.clone().or_else(|| other.raw_json.clone()),
+            raster_json: self.raster_json.clone().or_else(|| other.raster_json.clone()),
+            custom,
+        }
+    }
+
+    /// Returns the stored [`compression`](Self::compression), or if it's absent, the
+    /// [`inferred_compression`](Self::inferred_compression).
+    pub fn effective_compression(&self) -> Option<Compression> {
+        self.compression.or(self.inferred_compression)
+    }
+
+    /// Checks whether the `bounds`, or if they're absent, any part of the `antimeridian_bounds`, have no area.
+    pub(crate) fn has_degenerate_bounds(&self) -> bool {
+        let is_degenerate = |bounds: &GeoRect| {
+            let (tl, br) = (bounds.top_left(), bounds.bottom_right());
+            tl.lon() >= br.lon() || tl.lat() <= br.lat()
+        };
+        match (&self.bounds, &self.antimeridian_bounds) {
+            (Some(bounds), _) => is_degenerate(bounds),
+            (None, Some((west, east))) => is_degenerate(west) || is_degenerate(east),
+            (None, None) => false,
+        }
+    }
+
+    /// Checks the metadata for logical inconsistencies, returning all problems found.
+    pub fn validate(&self) -> Result<(), Vec<MetadataWarning>> {
+        let mut warnings = Vec::new();
+
+        if self.name.is_empty() {
+            warnings.push(MetadataWarning::EmptyName);
+        }
+
+        if let (Some(minzoom), Some(maxzoom)) = (self.minzoom, self.maxzoom) {
+            if minzoom > maxzoom {
+                warnings.push(MetadataWarning::InvertedZoomRange { minzoom, maxzoom });
+            }
+        }
+
+        if self.has_degenerate_bounds() {
+            warnings.push(MetadataWarning::DegenerateBounds);
+        }
+
+        if let Some((coord, zoom)) = &self.center {
+            let below = matches!(self.minzoom, Some(minzoom) if *zoom < minzoom);
+            let above = matches!(self.maxzoom, Some(maxzoom) if *zoom > maxzoom);
+            if below || above {
+                warnings.push(MetadataWarning::CenterZoomOutOfRange { zoom: *zoom });
+            }
+
+            let contains = |bounds: &GeoRect| {
+                let (tl, br) = (bounds.top_left(), bounds.bottom_right());
+                let (lon, lat) = (coord.lon(), coord.lat());
+                lon >= tl.lon() && lon <= br.lon() && lat >= br.lat() && lat <= tl.lat()
+            };
+            let in_bounds = match (&self.bounds, &self.antimeridian_bounds) {
+                (Some(bounds), _) => contains(bounds),
+                (None, Some((west, east))) => contains(west) || contains(east),
+                (None, None) => true,
+            };
+            if !in_bounds {
+                warnings.push(MetadataWarning::CenterOutOfBounds);
+            }
+        }
+
+        if let FileFormat::Pbf(mvt_metadata) = &self.format {
+            for layer in &mvt_metadata.vector_layers {
+                let below =
+                    matches!((layer.minzoom, self.minzoom), (Some(layer_zoom), Some(zoom)) if layer_zoom < zoom);
+                let above =
+                    matches!((layer.maxzoom, self.maxzoom), (Some(layer_zoom), Some(zoom)) if layer_zoom > zoom);
+                if below || above {
+                    warnings.push(MetadataWarning::LayerZoomOutOfRange {
+                        layer: layer.id.clone(),
+                        minzoom: layer.minzoom,
+                        maxzoom: layer.maxzoom,
+                    });
+                }
+            }
+        }
+
+        if warnings.is_empty() {
+            Ok(())
+        } else {
+            Err(warnings)
+        }
+    }
+}
+
+/// A problem found in [`Metadata`], see [`Metadata::validate`] and
+/// [`read_metadata_verbose`](crate::read::read_metadata_verbose).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataWarning {
+    /// The `name` is empty.
+    EmptyName,
+    /// `minzoom` is greater than `maxzoom`.
+    InvertedZoomRange { minzoom: u32, maxzoom: u32 },
+    /// The zoom level of `center` is outside the zoom levels of the tileset.
+    CenterZoomOutOfRange { zoom: u32 },
+    /// The coordinate of `center` is outside `bounds`.
+    CenterOutOfBounds,
+    /// The `bounds` (or a part of the `antimeridian_bounds`) have no area.
+    DegenerateBounds,
+    /// A key is stored in multiple rows, of which the last one is used.
+    DuplicateKey(String),
+    /// The value of a known key couldn't be parsed and is ignored.
+    MalformedValue {
+        key: String,
+        value: String,
+        reason: &'static str,
+    },
+    /// A key isn't defined by the specification, and is stored in [`Metadata::custom`].
+    UnknownKey(String),
+    /// A vector layer claims zoom levels outside the zoom levels of the tileset.
+    LayerZoomOutOfRange {
+        layer: String,
+        minzoom: Option<u32>,
+        maxzoom: Option<u32>,
+    },
+}
+
+impl fmt::Display for MetadataWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataWarning::EmptyName => write!(f, "name is empty"),
+            MetadataWarning::InvertedZoomRange { minzoom, maxzoom } => {
+                write!(f, "minzoom {} is greater than maxzoom {}", minzoom, maxzoom)
+            }
+            MetadataWarning::CenterZoomOutOfRange { zoom } => {
+                write!(f, "center zoom {} is outside the zoom range", zoom)
+            }
+            MetadataWarning::CenterOutOfBounds => write!(f, "center is outside the bounds"),
+            MetadataWarning::DegenerateBounds => write!(f, "bounds have no area"),
+            MetadataWarning::DuplicateKey(key) => write!(f, "duplicate key {}", key),
+            MetadataWarning::MalformedValue { key, value, reason } => {
+                write!(f, "malformed value {:?} of key {}: {}", value, key, reason)
+            }
+            MetadataWarning::UnknownKey(key) => write!(f, "unknown key {}", key),
+            MetadataWarning::LayerZoomOutOfRange { layer, .. } => {
+                write!(f, "zoom levels of vector layer {} are outside the zoom range", layer)
+            }
+        }
+    }
+}
+
+/// Serde adapters for the fields of [`Metadata`] which don't map directly to JSON.
+mod metadata_serde {
+    pub mod bounds {
+        use rosm_geo::coord::GeoCoord;
+        use rosm_geo::rect::GeoRect;
+
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(bounds: &Option<GeoRect>, serializer: S) -> Result<S::Ok, S::Error> {
+            let bounds = bounds.as_ref().map(|bounds| {
+                let (tl, br) = (bounds.top_left(), bounds.bottom_right());
+                [tl.lon(), br.lat(), br.lon(), tl.lat()]
+            });
+            bounds.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<GeoRect>, D::Error> {
+            let bounds: Option<[f64; 4]> = Option::deserialize(deserializer)?;
+            bounds
+                .map(|[west, south, east, north]| {
+                    let tl = GeoCoord::from_degrees(west, north).map_err(|_| D::Error::custom("invalid bounds"))?;
+                    let br = GeoCoord::from_degrees(east, south).map_err(|_| D::Error::custom("invalid bounds"))?;
+                    GeoRect::new(tl, br).map_err(|_| D::Error::custom("invalid bounds"))
+                })
+                .transpose()
+        }
+    }
+
+    pub mod antimeridian_bounds {
+        use rosm_geo::coord::GeoCoord;
+        use rosm_geo::rect::GeoRect;
+
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(bounds: &Option<(GeoRect, GeoRect)>, serializer: S) -> Result<S::Ok, S::Error> {
+            let bounds = bounds.as_ref().map(|(west, east)| {
+                let (tl, br) = (west.top_left(), east.bottom_right());
+                [tl.lon(), br.lat(), br.lon(), tl.lat()]
+            });
+            bounds.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(GeoRect, GeoRect)>, D::Error> {
+            let bounds: Option<[f64; 4]> = Option::deserialize(deserializer)?;
+            bounds
+                .map(|[west, south, east, north]| {
+                    let rect = |west: f64, east: f64| {
+                        let tl = GeoCoord::from_degrees(west, north).map_err(|_| D::Error::custom("invalid bounds"))?;
+                        let br = GeoCoord::from_degrees(east, south).map_err(|_| D::Error::custom("invalid bounds"))?;
+                        GeoRect::new(tl, br).map_err(|_| D::Error::custom("invalid bounds"))
+                    };
+                    Ok((rect(west, 180.0)?, rect(-180.0, east)?))
+                })
+                .transpose()
+        }
+    }
+
+    pub mod center {
+        use rosm_geo::coord::GeoCoord;
+
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(center: &Option<(GeoCoord, u32)>, serializer: S) -> Result<S::Ok, S::Error> {
+            let center = center.as_ref().map(|(coord, zoom)| (coord.lon(), coord.lat(), *zoom));
+            center.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(GeoCoord, u32)>, D::Error> {
+            let center: Option<(f64, f64, u32)> = Option::deserialize(deserializer)?;
+            center
+                .map(|(lon, lat, zoom)| {
+                    let coord = GeoCoord::from_degrees(lon, lat).map_err(|_| D::Error::custom("invalid center"))?;
+                    Ok((coord, zoom))
+                })
+                .transpose()
+        }
+    }
+}
+
+/// The well-known fields of the `json` metadata row of interactive raster tilesets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RasterJson {
+    /// The [Mustache](https://mustache.github.io/) template for formatting the UTFGrid data of a location.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// An HTML legend of the map.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legend: Option<String>,
+    /// URL templates of the UTFGrid interactivity grids.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grids: Option<Vec<String>>,
+}
+
+impl RasterJson {
+    fn is_empty(&self) -> bool {
+        self.template.is_none() && self.legend.is_none() && self.grids.is_none()
+    }
+
+    /// Parses the well-known fields from the given `json` row, returning `None` if none of them are present or the row
+    /// isn't a JSON object.
+    pub(crate) fn parse(json: &str) -> Option<RasterJson> {
+        serde_json::from_str::<RasterJson>(json)
+            .ok()
+            .filter(|raster_json| !raster_json.is_empty())
+    }
+
+    /// Merges the fields over the object in the given `json` row, returning the new row.
+    pub(crate) fn merge_into(&self, json: Option<&str>) -> serde_json::Result<String> {
+        let mut object = match json.map(serde_json::from_str::<serde_json::Value>).transpose()? {
+            Some(serde_json::Value::Object(object)) => object,
+            _ => serde_json::Map::new(),
+        };
+        if let serde_json::Value::Object(fields) = serde_json::to_value(self)? {
+            object.extend(fields);
+        }
+        serde_json::to_string(&object)
+    }
+}
+
 /// Additional metadata for [Mapbox Vector Tile](https://github.com/mapbox/vector-tile-spec) datasets.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MvtMetadata {
@@ -103,8 +959,75 @@ pub struct MvtMetadata {
     pub tilestats: Option<Tilestats>,
 }
 
+impl Clone for MvtMetadata {
+    fn clone(&self) -> Self {
+        // Tilestats are cloned through their serialized form, as they don't implement `Clone`.
+        let tilestats = self
+            .tilestats
+            .as_ref()
+            .and_then(|tilestats| serde_json::to_value(tilestats).ok())
+            .and_then(|tilestats| serde_json::from_value(tilestats).ok());
+
+        MvtMetadata {
+            vector_layers: self.vector_layers.clone(),
+            tilestats,
+        }
+    }
+}
+
+impl MvtMetadata {
+    /// Returns the attribute types of the layer with the given ID.
+    ///
+    /// The fields of the matching [`VectorLayer`] are preferred, but if they're missing or empty, the field types are
+    /// synthesized from the attributes of the layer in `tilestats`, as some tools only populate the latter. Attributes
+    /// with mixed or unknown types are reported as [`FieldType::String`].
+    pub fn fields_for_layer(&self, layer_id: &str) -> HashMap<String, FieldType> {
+        let fields = self
+            .vector_layers
+            .iter()
+            .find(|layer| layer.id == layer_id)
+            .map(|layer| &layer.fields)
+            .filter(|fields| !fields.is_empty());
+        if let Some(fields) = fields {
+            return fields.clone();
+        }
+
+        // Tilestats are read through their serialized form, which follows the mapbox-geostats format.
+        let tilestats = match self
+            .tilestats
+            .as_ref()
+            .and_then(|tilestats| serde_json::to_value(tilestats).ok())
+        {
+            Some(tilestats) => tilestats,
+            None => return HashMap::new(),
+        };
+
+        let layer = tilestats["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|layer| layer["layer"].as_str() == Some(layer_id));
+
+        let attributes = layer
+            .and_then(|layer| layer["attributes"].as_array())
+            .into_iter()
+            .flatten();
+        attributes
+            .filter_map(|attribute| {
+                let name = attribute["attribute"].as_str()?;
+                let field_type = match attribute["type"].as_str() {
+                    Some("number") => FieldType::Number,
+                    Some("boolean") => FieldType::Boolean,
+                    _ => FieldType::String,
+                };
+                Some((name.to_owned(), field_type))
+            })
+            .collect()
+    }
+}
+
 /// Description for a specific layer of vector tile data.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VectorLayer {
     /// The layer ID, which is referred to as the name of the layer in the [Mapbox Vector Tile spec](https://github.com/mapbox/vector-tile-spec).
     pub id: String,
@@ -125,12 +1048,690 @@ pub struct VectorLayer {
     pub maxzoom: Option<u32>,
 }
 
+/// A [UTFGrid](https://github.com/mapbox/utfgrid-spec) of a tile.
+#[derive(Debug, Default)]
+pub struct UtfGrid {
+    /// The rows of the grid, with each character encoding the index of a key.
+    pub grid: Vec<String>,
+    /// The keys the grid refers to, starting with the empty key for cells without data.
+    pub keys: Vec<String>,
+    /// The data of the keys, stored separately in the `grid_data` table.
+    pub data: HashMap<String, serde_json::Value>,
+}
+
 /// Layer attribute type.
 ///
 /// **Note:** attributes with mixed types should be serialized as string.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum FieldType {
     Number,
     Boolean,
     String,
 }
+
+/// The officially assigned MBTiles magic number, used as SQLite application ID.
+pub const MBTILES_APPLICATION_ID: i32 = 0x4d504258;
+
+/// The highest zoom level supported by the crate.
+pub const MAX_ZOOM_LEVEL: u32 = 30;
+
+/// The GZIP compression level used by [`gzip`], balancing speed and size.
+pub const DEFAULT_GZIP_LEVEL: u32 = 6;
+
+/// Compresses the given data with GZIP, using [`DEFAULT_GZIP_LEVEL`].
+#[cfg(feature = "compression")]
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    gzip_with_level(data, DEFAULT_GZIP_LEVEL)
+}
+
+/// Compresses the given data with GZIP, using the given compression level.
+///
+/// Levels range from 0 (no compression) through 1 (fastest) to 9 (smallest output), higher levels are clamped to 9.
+/// Lower levels suit live updates, while final archive builds benefit from the highest ones.
+#[cfg(feature = "compression")]
+pub fn gzip_with_level(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+    encoder.write_all(data).expect("writing into a Vec can't fail");
+    encoder.finish().expect("writing into a Vec can't fail")
+}
+
+/// Decompresses the given GZIP-compressed data.
+#[cfg(feature = "compression")]
+pub fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(feature = "compression")]
+impl Compression {
+    /// Compresses the given data, with the default level of the compression. [`Compression::None`] returns the data
+    /// unchanged.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let level = match self {
+            Compression::None => 0,
+            Compression::Gzip => DEFAULT_GZIP_LEVEL,
+            Compression::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL as u32,
+            Compression::Brotli => 9,
+        };
+        self.compress_with_level(data, level)
+    }
+
+    /// Compresses the given data with the given level, which is clamped into the levels of the compression: 0 to 9
+    /// for GZIP (see [`gzip_with_level`]), 1 to 22 for Zstandard and 0 to 11 for Brotli, with higher levels producing
+    /// smaller output more slowly. [`Compression::None`] returns the data unchanged.
+    pub fn compress_with_level(&self, data: &[u8], level: u32) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => gzip_with_level(data, level),
+            Compression::Zstd => {
+                let level = level.max(1).min(22) as i32;
+                zstd::encode_all(data, level).expect("writing into a Vec can't fail")
+            }
+            Compression::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level.min(11), 22);
+                encoder.write_all(data).expect("writing into a Vec can't fail");
+                encoder.into_inner()
+            }
+        }
+    }
+
+    /// Decompresses the given data, which must be compressed with this compression. [`Compression::None`] returns the
+    /// data unchanged.
+    pub fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => gunzip(data),
+            Compression::Zstd => zstd::decode_all(data),
+            Compression::Brotli => {
+                let mut decompressed = Vec::new();
+                brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+/// A content hash identifying tile data in the deduplicated `images` table, stored in its `tile_id` column.
+///
+/// Implemented for closures, e.g. to compute a cryptographic digest with a hashing crate of choice:
+///
+/// ```ignore
+/// let sha256 = |tile_data: &[u8]| hex::encode(Sha256::digest(tile_data));
+/// ```
+pub trait TileHasher {
+    /// Returns the hash of the given tile data.
+    fn hash(&self, tile_data: &[u8]) -> String;
+}
+
+impl<F: Fn(&[u8]) -> String> TileHasher for F {
+    fn hash(&self, tile_data: &[u8]) -> String {
+        self(tile_data)
+    }
+}
+
+/// The default [`TileHasher`], a hexadecimal 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash.
+///
+/// **Note:** the hash is fast but not cryptographic. Tiles whose hashes collide end up sharing the data of the tile
+/// written first, which becomes likely only with billions of distinct tiles but can be provoked deliberately. Use a
+/// cryptographic hash like SHA-256 if that matters, or if the `tile_id` should be a verifiable digest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fnv1aHasher;
+
+impl TileHasher for Fnv1aHasher {
+    fn hash(&self, tile_data: &[u8]) -> String {
+        content_hash(tile_data)
+    }
+}
+
+/// The `tile_id` of the canonical empty tile in the deduplicated `images` table, which can't collide with the
+/// hexadecimal hashes of [`Fnv1aHasher`].
+pub const EMPTY_TILE_ID: &str = "empty";
+
+/// Returns the content hash identifying the given tile data in the deduplicated `images` table, as a hexadecimal
+/// 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash.
+pub(crate) fn content_hash(data: &[u8]) -> String {
+    format!("{:016x}", fnv1a(data))
+}
+
+/// Returns the 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash of the given data.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Statistics of a database using the deduplicating `map`/`images` layout.
+#[derive(Debug)]
+pub struct DedupStats {
+    /// The number of tiles, i.e. rows of the `map` table.
+    pub map_rows: u64,
+    /// The number of distinct tile data blobs, i.e. rows of the `images` table.
+    pub image_rows: u64,
+    /// The total size of the tile data in bytes, as if each tile was stored separately.
+    pub tile_bytes: u64,
+    /// The total size of the distinct tile data blobs in bytes.
+    pub image_bytes: u64,
+}
+
+impl DedupStats {
+    /// Returns the number of tiles which share their data with another tile.
+    pub fn saved_tiles(&self) -> u64 {
+        self.map_rows.saturating_sub(self.image_rows)
+    }
+
+    /// Returns the number of tile data bytes saved by deduplication.
+    pub fn saved_bytes(&self) -> u64 {
+        self.tile_bytes.saturating_sub(self.image_bytes)
+    }
+
+    /// Returns the average number of tiles referencing each distinct tile data blob, which is 1 if deduplication
+    /// saves nothing.
+    pub fn ratio(&self) -> f64 {
+        if self.image_rows == 0 {
+            1.0
+        } else {
+            self.map_rows as f64 / self.image_rows as f64
+        }
+    }
+}
+
+/// A handle to an SQLite connection, which allows the reading functions to be called with any of them.
+///
+/// Implemented for connections, transactions and savepoints, and can be implemented for connection wrappers, e.g. of
+/// connection pools.
+pub trait AsConnection {
+    /// Returns the underlying connection.
+    fn as_connection(&self) -> &rusqlite::Connection;
+}
+
+impl AsConnection for rusqlite::Connection {
+    fn as_connection(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+
+impl AsConnection for rusqlite::Transaction<'_> {
+    fn as_connection(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+
+impl AsConnection for rusqlite::Savepoint<'_> {
+    fn as_connection(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+
+/// A zoom level supported by the crate, i.e. at most [`MAX_ZOOM_LEVEL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ZoomLevel(u8);
+
+impl ZoomLevel {
+    /// Returns the zoom level as a number.
+    pub fn get(self) -> u32 {
+        u32::from(self.0)
+    }
+}
+
+impl TryFrom<u32> for ZoomLevel {
+    type Error = InvalidZoomLevel;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if value <= MAX_ZOOM_LEVEL {
+            Ok(ZoomLevel(value as u8))
+        } else {
+            Err(InvalidZoomLevel(value))
+        }
+    }
+}
+
+impl From<ZoomLevel> for u32 {
+    fn from(zoom: ZoomLevel) -> Self {
+        zoom.get()
+    }
+}
+
+impl fmt::Display for ZoomLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Error for zoom levels above [`MAX_ZOOM_LEVEL`].
+#[derive(Debug)]
+pub struct InvalidZoomLevel(pub u32);
+
+impl fmt::Display for InvalidZoomLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid zoom level {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidZoomLevel {}
+
+/// Error for tile coordinates which don't address a tile.
+#[derive(Debug)]
+pub struct InvalidTileCoord {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl fmt::Display for InvalidTileCoord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid tile coordinates {}/{}/{}", self.z, self.x, self.y)
+    }
+}
+
+impl std::error::Error for InvalidTileCoord {}
+
+/// Creates a tile ID from the `zoom_level`, `tile_column` and `tile_row` values of a database row, which use the TMS
+/// scheme.
+///
+/// Returns an error if the zoom level is above [`MAX_ZOOM_LEVEL`], or the column or row is out of range for the zoom
+/// level.
+pub fn tile_id_from_row(z: u32, x: u32, y: u32) -> Result<TmsTileId, InvalidTileCoord> {
+    if z > MAX_ZOOM_LEVEL || x >> z != 0 || y >> z != 0 {
+        return Err(InvalidTileCoord { z, x, y });
+    }
+
+    // TMS and XYZ rows only differ in direction.
+    let tile_id = TileId::new(z, x, (1 << z) - 1 - y).map_err(|_| InvalidTileCoord { z, x, y })?;
+    Ok(tile_id.into())
+}
+
+/// Creates a tile ID from XYZ scheme coordinates, as used by most web maps.
+///
+/// Returns an error if the zoom level is above [`MAX_ZOOM_LEVEL`], or the column or row is out of range for the zoom
+/// level.
+pub fn tile_id_from_xyz(z: u32, x: u32, y: u32) -> Result<TmsTileId, InvalidTileCoord> {
+    if z > MAX_ZOOM_LEVEL || x >> z != 0 || y >> z != 0 {
+        return Err(InvalidTileCoord { z, x, y });
+    }
+
+    let tile_id = TileId::new(z, x, y).map_err(|_| InvalidTileCoord { z, x, y })?;
+    Ok(tile_id.into())
+}
+
+/// The latitude limit of the Web Mercator projection, in degrees.
+pub(crate) const MAX_MERCATOR_LAT: f64 = 85.051_128_779_806_59;
+
+/// Returns the column and row ranges of the tiles covering the given rectangle at the given zoom level.
+///
+/// **Note:** the rows are [TMS](https://wiki.osgeo.org/wiki/Tile_Map_Service_Specification) rows counted from the
+/// south like the `tile_row` column of the `tiles` table, not XYZ rows counted from the north. XYZ rows can be
+/// obtained as `(1 << zoom) - 1 - row`, which also reverses the range.
+///
+/// Parts of the rectangle beyond the latitude limit of the Web Mercator projection are clamped to the outermost rows.
+pub fn rect_to_tile_range(rect: &GeoRect, zoom: u32) -> (RangeInclusive<u32>, RangeInclusive<u32>) {
+    let tl = rect.top_left();
+    let br = rect.bottom_right();
+    extent_to_tile_range(tl.lon(), br.lat(), br.lon(), tl.lat(), zoom)
+}
+
+/// Returns the column and TMS row ranges of the tiles covering the given extent (in degrees) at the given zoom level.
+pub(crate) fn extent_to_tile_range(
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+    zoom: u32,
+) -> (RangeInclusive<u32>, RangeInclusive<u32>) {
+    let columns = lon_to_column(west, zoom)..=lon_to_column(east, zoom);
+    // TMS rows grow northwards.
+    let rows = lat_to_row(south, zoom)..=lat_to_row(north, zoom);
+    (columns, rows)
+}
+
+fn lon_to_column(lon: f64, zoom: u32) -> u32 {
+    let n = (1u64 << zoom) as f64;
+    let column = ((lon + 180.0) / 360.0 * n).floor();
+    column.max(0.0).min(n - 1.0) as u32
+}
+
+fn lat_to_row(lat: f64, zoom: u32) -> u32 {
+    let n = (1u64 << zoom) as f64;
+    let lat = lat.max(-MAX_MERCATOR_LAT).min(MAX_MERCATOR_LAT).to_radians();
+    let xyz_row = ((1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n).floor();
+    (n - 1.0 - xyz_row.max(0.0).min(n - 1.0)) as u32
+}
+
+/// Returns the `(west, south, east, north)` extent of the given TMS tile, in degrees.
+pub(crate) fn tile_extent(zoom: u32, column: u32, row: u32) -> (f64, f64, f64, f64) {
+    let n = (1u64 << zoom) as f64;
+    let lon = |column: f64| column / n * 360.0 - 180.0;
+    // The southern edge of a TMS row.
+    let lat = |row: f64| (PI * (2.0 * row / n - 1.0)).sinh().atan().to_degrees();
+
+    let (column, row) = (column as f64, row as f64);
+    (lon(column), lat(row), lon(column + 1.0), lat(row + 1.0))
+}
+
+/// Checks whether the given `(lon, lat)` point is inside the polygon given by its vertices, using ray casting.
+pub(crate) fn polygon_contains(vertices: &[(f64, f64)], (lon, lat): (f64, f64)) -> bool {
+    let mut inside = false;
+
+    for (i, &(lon_i, lat_i)) in vertices.iter().enumerate() {
+        let (lon_j, lat_j) = vertices[(i + vertices.len() - 1) % vertices.len()];
+        if (lat_i > lat) != (lat_j > lat) && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Checks whether the polygon given by its vertices intersects the given `(west, south, east, north)` extent.
+///
+/// Either a vertex of the polygon is within the extent, a corner of the extent is inside the polygon, or, if neither,
+/// an edge of the polygon crosses an edge of the extent.
+pub(crate) fn polygon_intersects_extent(
+    vertices: &[(f64, f64)],
+    (west, south, east, north): (f64, f64, f64, f64),
+) -> bool {
+    let corners = [(west, south), (east, south), (east, north), (west, north)];
+
+    let contains_vertex = vertices
+        .iter()
+        .any(|&(lon, lat)| west <= lon && lon <= east && south <= lat && lat <= north);
+    if contains_vertex || corners.iter().any(|&corner| polygon_contains(vertices, corner)) {
+        return true;
+    }
+
+    let edges = |points: &[(f64, f64)]| {
+        let len = points.len();
+        (0..len)
+            .map(move |i| (points[i], points[(i + 1) % len]))
+            .collect::<Vec<_>>()
+    };
+    let extent_edges = edges(&corners[..]);
+    edges(vertices).into_iter().any(|edge| {
+        extent_edges
+            .iter()
+            .any(|&extent_edge| segments_cross(edge, extent_edge))
+    })
+}
+
+/// Checks whether the two line segments cross each other, i.e. the endpoints of each are strictly on opposite sides
+/// of the other.
+fn segments_cross((a, b): ((f64, f64), (f64, f64)), (c, d): ((f64, f64), (f64, f64))) -> bool {
+    let side = |(p, q): ((f64, f64), (f64, f64)), r: (f64, f64)| (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0);
+    side((a, b), c) * side((a, b), d) < 0.0 && side((c, d), a) * side((c, d), b) < 0.0
+}
+
+#[cfg(test)]
+mod mbtiles_common_test {
+    use super::*;
+
+    #[test]
+    fn build_tilejson() {
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            format: FileFormat::Png,
+            minzoom: Some(0),
+            maxzoom: Some(4),
+            version: Some(2),
+            ..Default::default()
+        };
+
+        let tilejson = metadata.to_tilejson("https://example.com/{z}/{x}/{y}.png").unwrap();
+
+        assert_eq!(
+            tilejson,
+            serde_json::json!({
+                "tilejson": "3.0.0",
+                "tiles": ["https://example.com/{z}/{x}/{y}.png"],
+                "name": "test",
+                "version": "2.0.0",
+                "minzoom": 0,
+                "maxzoom": 4
+            })
+        );
+    }
+
+    #[test]
+    fn read_image_details() {
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 13];
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&[0, 0, 1, 0, 0, 0, 1, 0, 8, 3, 0, 0, 0]);
+
+        let details = image_details(&png).unwrap();
+        assert_eq!((details.width, details.height), (256, 256));
+        assert_eq!(details.bit_depth, Some(8));
+        assert!(details.is_indexed());
+
+        assert!(image_details(&png[..20]).is_none());
+        assert!(image_details(b"not an image").is_none());
+    }
+
+    #[test]
+    fn fields_from_tilestats() {
+        let tilestats = serde_json::json!({
+            "layerCount": 1,
+            "layers": [{
+                "layer": "roads",
+                "count": 1,
+                "geometry": "LineString",
+                "attributeCount": 3,
+                "attributes": [
+                    { "attribute": "name", "count": 1, "type": "string", "values": ["Main"] },
+                    { "attribute": "lanes", "count": 1, "type": "number", "values": [2], "min": 2, "max": 2 },
+                    { "attribute": "ref", "count": 2, "type": "mixed", "values": ["A1", 1] },
+                ],
+            }],
+        });
+        let mut mvt_metadata = MvtMetadata {
+            vector_layers: Vec::new(),
+            tilestats: Some(serde_json::from_value(tilestats).unwrap()),
+        };
+
+        let fields = mvt_metadata.fields_for_layer("roads");
+        assert_eq!(fields.len(), 3);
+        assert!(matches!(fields["name"], FieldType::String));
+        assert!(matches!(fields["lanes"], FieldType::Number));
+        assert!(matches!(fields["ref"], FieldType::String));
+        assert!(mvt_metadata.fields_for_layer("water").is_empty());
+
+        mvt_metadata.vector_layers.push(VectorLayer {
+            id: "roads".to_owned(),
+            fields: vec![("lanes".to_owned(), FieldType::String)].into_iter().collect(),
+            description: String::new(),
+            minzoom: None,
+            maxzoom: None,
+        });
+
+        let fields = mvt_metadata.fields_for_layer("roads");
+        assert_eq!(fields.len(), 1);
+        assert!(matches!(fields["lanes"], FieldType::String));
+    }
+
+    #[test]
+    fn antimeridian_tilejson_and_center() {
+        let rect = |west, south, east, north| {
+            let tl = GeoCoord::from_degrees(west, north).unwrap();
+            let br = GeoCoord::from_degrees(east, south).unwrap();
+            GeoRect::new(tl, br).unwrap()
+        };
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            antimeridian_bounds: Some((rect(170.0, -10.0, 180.0, 10.0), rect(-180.0, -10.0, -160.0, 10.0))),
+            ..Default::default()
+        };
+
+        let tilejson = metadata.to_tilejson("https://example.com/{z}/{x}/{y}.png").unwrap();
+        assert_eq!(tilejson["bounds"], serde_json::json!([170.0, -10.0, -160.0, 10.0]));
+
+        let (coord, zoom) = metadata.effective_center().unwrap();
+        assert!((coord.lon() - -175.0).abs() < 1e-9);
+        assert!(coord.lat().abs() < 1e-9);
+        assert_eq!(zoom, 3);
+    }
+
+    #[test]
+    fn merge_metadata() {
+        let rect = |west, south, east, north| {
+            GeoRect::new(
+                GeoCoord::from_degrees(west, north).unwrap(),
+                GeoCoord::from_degrees(east, south).unwrap(),
+            )
+            .unwrap()
+        };
+
+        let a = Metadata {
+            bounds: Some(rect(0.0, 0.0, 10.0, 10.0)),
+            minzoom: Some(2),
+            maxzoom: Some(4),
+            description: Some(String::new()),
+            version: Some(1),
+            ..Default::default()
+        };
+        let b = Metadata {
+            name: "b".to_owned(),
+            bounds: Some(rect(-5.0, 5.0, 5.0, 20.0)),
+            minzoom: Some(0),
+            maxzoom: Some(3),
+            description: Some("b".to_owned()),
+            version: Some(2),
+            ..Default::default()
+        };
+
+        let merged = a.merged_with(&b);
+
+        let bounds = merged.bounds.unwrap();
+        assert_eq!(bounds.top_left().lon(), -5.0);
+        assert_eq!(bounds.top_left().lat(), 20.0);
+        assert_eq!(bounds.bottom_right().lon(), 10.0);
+        assert_eq!(bounds.bottom_right().lat(), 0.0);
+        assert_eq!(merged.zoom_range(), Some(0..=4));
+        assert_eq!(merged.name, "b");
+        assert_eq!(merged.description.as_deref(), Some("b"));
+        assert_eq!(merged.version, Some(1));
+    }
+
+    #[test]
+    fn validate_metadata() {
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            bounds: Some(
+                GeoRect::new(
+                    GeoCoord::from_degrees(-10.0, 10.0).unwrap(),
+                    GeoCoord::from_degrees(10.0, -10.0).unwrap(),
+                )
+                .unwrap(),
+            ),
+            center: Some((GeoCoord::from_degrees(0.0, 0.0).unwrap(), 2)),
+            minzoom: Some(0),
+            maxzoom: Some(4),
+            ..Default::default()
+        };
+        assert!(metadata.validate().is_ok());
+
+        let metadata = Metadata {
+            name: String::new(),
+            center: Some((GeoCoord::from_degrees(20.0, 0.0).unwrap(), 6)),
+            minzoom: Some(5),
+            maxzoom: Some(4),
+            ..metadata
+        };
+        assert_eq!(
+            metadata.validate(),
+            Err(vec![
+                MetadataWarning::EmptyName,
+                MetadataWarning::InvertedZoomRange { minzoom: 5, maxzoom: 4 },
+                MetadataWarning::CenterZoomOutOfRange { zoom: 6 },
+                MetadataWarning::CenterOutOfBounds,
+            ])
+        );
+    }
+
+    #[test]
+    fn detect_tile_compression() {
+        assert_eq!(detect_compression(&[0x1f, 0x8b, 0x08, 0x00]), Compression::Gzip);
+        assert_eq!(detect_compression(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Compression::Zstd);
+        assert_eq!(
+            detect_compression(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a]),
+            Compression::None
+        );
+        assert_eq!(detect_compression(&[0x1a, 0x10, 0x78, 0x02]), Compression::None);
+        assert_eq!(detect_compression(&[]), Compression::None);
+        assert_eq!(detect_compression(&[0x0b, 0x02, 0x80]), Compression::Brotli);
+    }
+
+    #[test]
+    fn validate_media_type() {
+        assert!(FileFormat::other("image/avif").is_ok());
+        assert!(FileFormat::other("application/vnd.mapbox-vector-tile").is_ok());
+        assert!(FileFormat::other("imag/png").is_err());
+        assert!(FileFormat::other("image/").is_err());
+        assert!(FileFormat::other("png").is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_round_trip() {
+        let data = b"tile data tile data tile data";
+        for compression in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Zstd,
+            Compression::Brotli,
+        ]
+        .iter()
+        {
+            let compressed = compression.compress(data);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+            let compressed = compression.compress_with_level(data, 1);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn fnv1a_content_hash() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn tile_range_of_extent() {
+        let (columns, rows) = extent_to_tile_range(-180.0, -MAX_MERCATOR_LAT, 180.0, MAX_MERCATOR_LAT, 2);
+        assert_eq!((columns, rows), (0..=3, 0..=3));
+
+        // The north-eastern quarter of the world is the top right tile, which is the last TMS row.
+        let (columns, rows) = extent_to_tile_range(1.0, 1.0, 179.0, 80.0, 1);
+        assert_eq!((columns, rows), (1..=1, 1..=1));
+    }
+
+    #[test]
+    fn point_in_polygon() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+
+        assert!(polygon_contains(&square, (5.0, 5.0)));
+        assert!(!polygon_contains(&square, (15.0, 5.0)));
+        assert!(!polygon_contains(&square, (5.0, -1.0)));
+    }
+
+    #[test]
+    fn polygon_extent_intersection() {
+        let extent = (0.0, 0.0, 10.0, 10.0);
+
+        // Within the extent, covering the extent, and a band crossing it without any vertex or corner inside
+        assert!(polygon_intersects_extent(&[(2.0, 2.0), (3.0, 2.0), (2.5, 3.0)], extent));
+        assert!(polygon_intersects_extent(
+            &[(-5.0, -5.0), (15.0, -5.0), (15.0, 15.0), (-5.0, 15.0)],
+            extent
+        ));
+        assert!(polygon_intersects_extent(
+            &[(-5.0, 4.0), (15.0, 4.0), (15.0, 6.0), (-5.0, 6.0)],
+            extent
+        ));
+
+        assert!(!polygon_intersects_extent(
+            &[(12.0, 2.0), (13.0, 2.0), (12.5, 3.0)],
+            extent
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 //! A Rust library for reading and writing [MBTiles](https://github.com/mapbox/mbtiles-spec) databases.
 
 pub mod common;
+#[cfg(feature = "mvt")]
+pub mod mvt;
 pub mod read;
 pub mod write;
@@ -0,0 +1,224 @@
+//! Functions for inspecting the contents of [Mapbox Vector Tile](https://github.com/mapbox/vector-tile-spec) tiles.
+//!
+//! Only the layer structure is decoded, feature geometries and attribute values are skipped.
+
+use rosm_geo::mercator::TmsTileId;
+
+use std::error::Error;
+use std::fmt;
+
+use crate::common::{detect_compression, AsConnection};
+use crate::read::tiles;
+
+/// Error for tile data which is not a valid vector tile.
+#[derive(Debug)]
+pub struct InvalidVectorTile(pub &'static str);
+
+impl fmt::Display for InvalidVectorTile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid vector tile: {}", self.0)
+    }
+}
+
+impl Error for InvalidVectorTile {}
+
+/// The layer structure of a vector tile.
+#[derive(Debug, Default)]
+pub struct DecodedTile {
+    /// The layers of the tile, in the order they're stored.
+    pub layers: Vec<DecodedLayer>,
+}
+
+/// A layer of a vector tile.
+#[derive(Debug)]
+pub struct DecodedLayer {
+    /// The name of the layer, which is the ID of the matching [`VectorLayer`](crate::common::VectorLayer).
+    pub name: String,
+    /// The version of the vector tile specification the layer conforms to.
+    pub version: u32,
+    /// The size of the tile in the coordinate space of the layer.
+    pub extent: u32,
+    /// The number of features in the layer.
+    pub feature_count: usize,
+    /// The attribute keys used by the features of the layer.
+    pub keys: Vec<String>,
+}
+
+impl Default for DecodedLayer {
+    fn default() -> Self {
+        DecodedLayer {
+            name: String::new(),
+            version: 1,
+            extent: 4096,
+            feature_count: 0,
+            keys: Vec::new(),
+        }
+    }
+}
+
+/// Decodes the layer structure of the given uncompressed vector tile.
+pub fn decode_tile(data: &[u8]) -> Result<DecodedTile, InvalidVectorTile> {
+    let mut tile = DecodedTile::default();
+
+    let mut reader = Reader { data };
+    while let Some((field, value)) = reader.next_field()? {
+        if let (3, Value::Bytes(layer)) = (field, value) {
+            tile.layers.push(decode_layer(layer)?);
+        }
+    }
+
+    Ok(tile)
+}
+
+fn decode_layer(data: &[u8]) -> Result<DecodedLayer, InvalidVectorTile> {
+    let mut layer = DecodedLayer::default();
+
+    let mut reader = Reader { data };
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, Value::Bytes(name)) => layer.name = decode_string(name)?,
+            (2, Value::Bytes(_)) => layer.feature_count += 1,
+            (3, Value::Bytes(key)) => layer.keys.push(decode_string(key)?),
+            (5, Value::Varint(extent)) => layer.extent = extent as u32,
+            (15, Value::Varint(version)) => layer.version = version as u32,
+            _ => {}
+        }
+    }
+
+    Ok(layer)
+}
+
+fn decode_string(data: &[u8]) -> Result<String, InvalidVectorTile> {
+    String::from_utf8(data.to_vec()).map_err(|_| InvalidVectorTile("string is not valid UTF-8"))
+}
+
+/// Returns an iterator over all tiles in the database with their decoded layer structure, see [`tiles`].
+///
+/// Compressed tiles are decompressed before decoding, detecting their compression like [`detect_compression`].
+pub fn decoded_tiles(
+    conn: &impl AsConnection,
+) -> impl Iterator<Item = Result<(TmsTileId, DecodedTile), Box<dyn Error>>> + '_ {
+    tiles(conn).map(|tile| {
+        let (tile_id, tile_data) = tile?;
+        let tile_data = detect_compression(&tile_data).decompress(&tile_data)?;
+        Ok((tile_id, decode_tile(&tile_data)?))
+    })
+}
+
+/// A field value of a protocol buffers message, with fixed size values skipped.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Reads the fields of an encoded protocol buffers message.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>, InvalidVectorTile> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            _ => return Err(InvalidVectorTile("unsupported wire type")),
+        };
+
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, InvalidVectorTile> {
+        let mut value = 0;
+        for (i, &byte) in self.data.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(InvalidVectorTile("truncated varint"))
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8], InvalidVectorTile> {
+        if len > self.data.len() as u64 {
+            return Err(InvalidVectorTile("truncated field"));
+        }
+        let (taken, rest) = self.data.split_at(len as usize);
+        self.data = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod mbtiles_mvt_test {
+    use super::*;
+    use crate::common::Compression;
+
+    #[test]
+    fn decode_layer_structure() {
+        let data = [
+            0x1a, 0x0a, // Layer, 10 bytes
+            0x78, 0x02, // Version 2
+            0x0a, 0x01, b'a', // Name "a"
+            0x12, 0x00, // Empty feature
+            0x28, 0x80, 0x20, // Extent 4096
+        ];
+
+        let tile = decode_tile(&data).unwrap();
+
+        assert_eq!(tile.layers.len(), 1);
+        assert_eq!(tile.layers[0].name, "a");
+        assert_eq!(tile.layers[0].version, 2);
+        assert_eq!(tile.layers[0].extent, 4096);
+        assert_eq!(tile.layers[0].feature_count, 1);
+
+        assert!(decode_tile(&data[..5]).is_err());
+    }
+
+    #[test]
+    fn decode_compressed_tiles() {
+        let data = [0x1a, 0x05, 0x78, 0x02, 0x0a, 0x01, b'a'];
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB)",
+            [],
+        )
+        .unwrap();
+        for (x, compression) in [Compression::None, Compression::Gzip, Compression::Zstd]
+            .iter()
+            .enumerate()
+        {
+            conn.execute(
+                "INSERT INTO tiles VALUES (2, ?1, 0, ?2)",
+                rusqlite::params![x as u32, compression.compress(&data)],
+            )
+            .unwrap();
+        }
+
+        let tiles: Vec<_> = decoded_tiles(&conn).map(|tile| tile.unwrap()).collect();
+        assert_eq!(tiles.len(), 3);
+        for (tile_id, tile) in tiles {
+            assert_eq!(tile.layers.len(), 1, "tile at column {}", tile_id.x());
+            assert_eq!(tile.layers[0].name, "a");
+            assert_eq!(tile.layers[0].version, 2);
+        }
+    }
+}
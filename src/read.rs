@@ -1,91 +1,233 @@
 //! Functions for reading MBTiles databases.
 
 use rosm_geo::coord::GeoCoord;
-use rosm_geo::mercator::TmsTileId;
+use rosm_geo::mercator::{TileId, TmsTileId};
 use rosm_geo::rect::GeoRect;
 
-use rusqlite::params;
+use rosm_geostats::Tilestats;
 
+use rusqlite::types::{FromSql, ValueRef};
+use rusqlite::{params, params_from_iter, OpenFlags, Params};
+
+use serde::Deserialize;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "compression")]
+use crate::common::gunzip;
+use crate::common::{
+    detect_compression, detect_media_type, extent_to_tile_range, fnv1a, image_details, polygon_intersects_extent,
+    recognize_compression, rect_to_tile_range, tile_extent, tile_id_from_row, tile_id_from_xyz, AsConnection,
+    Compression, DedupStats, FileFormat, ImageDetails, InvalidTileCoord, Metadata, MetadataWarning, MvtMetadata,
+    RasterJson, Scheme, Type, VectorLayer, ZoomLevel, EMPTY_TILE_ID, MBTILES_APPLICATION_ID,
+};
+
+/// Error for a metadata value which is present but malformed.
+#[derive(Debug)]
+pub struct MalformedMetadata {
+    /// The metadata key, e.g. `bounds`.
+    pub key: String,
+    /// The raw value as stored in the database.
+    pub value: String,
+    /// Why the value couldn't be parsed.
+    pub reason: &'static str,
+}
+
+impl MalformedMetadata {
+    fn new(key: &str, value: String, reason: &'static str) -> Self {
+        MalformedMetadata {
+            key: key.to_owned(),
+            value,
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for MalformedMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "malformed `{}` metadata value {:?}: {}",
+            self.key, self.value, self.reason
+        )
+    }
+}
+
+impl Error for MalformedMetadata {}
+
+/// Opens the database at the given path in read-only mode.
+pub fn open_read_only<P: AsRef<Path>>(path: P) -> rusqlite::Result<rusqlite::Connection> {
+    rusqlite::Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+/// Opens the database at the given path in read-only mode, through the SQLite
+/// [VFS](https://www.sqlite.org/vfs.html) registered with the given name.
+///
+/// This allows reading databases which aren't local files with all the functions of this module, e.g. tilesets
+/// hosted over HTTP through a VFS issuing range requests, like
+/// [sqlite_web_vfs](https://github.com/mlin/sqlite_web_vfs). The VFS must be registered before opening the database.
+pub fn open_with_vfs<P: AsRef<Path>>(path: P, vfs_name: &str) -> rusqlite::Result<rusqlite::Connection> {
+    rusqlite::Connection::open_with_flags_and_vfs(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        vfs_name,
+    )
+}
+
+/// Sets the maximum number of bytes of the database file to access through memory-mapped I/O, with
+/// `PRAGMA mmap_size`.
+///
+/// Memory mapping saves a system call and a copy per page read, which speeds up random tile access in large databases,
+/// especially together with [`open_read_only`]. A value covering the whole file (e.g. `1 << 30` for databases up to a
+/// gigabyte) works well on 64-bit systems, while 0 disables memory mapping. The value is capped by SQLite's compile
+/// time limit, and it's a no-op if SQLite was built without memory mapping support.
+pub fn set_mmap_size(conn: &impl AsConnection, bytes: i64) -> rusqlite::Result<()> {
+    conn.as_connection().pragma_update(None, "mmap_size", &bytes)
+}
+
+/// Quickly checks whether the file at the given path is plausibly an MBTiles database, without validating its schema.
+///
+/// The file is considered an MBTiles database if its application ID is the MBTiles magic number, or if its
+/// application ID is unset but it has a `tiles` table, as many databases in the wild never set it.
+pub fn is_mbtiles<P: AsRef<Path>>(path: P) -> bool {
+    fn check(path: &Path) -> rusqlite::Result<bool> {
+        let conn = open_read_only(path)?;
+        let application_id: i32 = conn.query_row("PRAGMA application_id", [], |row| row.get(0))?;
+        match application_id {
+            MBTILES_APPLICATION_ID => Ok(true),
+            0 => has_tiles(&conn),
+            _ => Ok(false),
+        }
+    }
 
-use crate::common::{FileFormat, Metadata, MvtMetadata, Type};
+    check(path.as_ref()).unwrap_or(false)
+}
 
 /// Reads metadata from the given database.
-pub fn read_metadata(conn: &rusqlite::Connection) -> Result<Metadata, Box<dyn std::error::Error>> {
-    let mut select_metadata = conn.prepare_cached("SELECT name, value FROM metadata")?;
+///
+/// Malformed `bounds` and `center` values are ignored, see [`read_metadata_strict`] for a validating variant.
+pub fn read_metadata(conn: &impl AsConnection) -> Result<Metadata, Box<dyn Error>> {
+    let conn = conn.as_connection();
+    Ok(read_metadata_impl(conn, "metadata", "tiles", false)?.0)
+}
+
+/// Opens the database at the given path in read-only mode and reads its metadata, see [`open_read_only`] and
+/// [`read_metadata`].
+///
+/// The connection is closed before returning.
+pub fn metadata_of<P: AsRef<Path>>(path: P) -> Result<Metadata, Box<dyn Error>> {
+    let conn = open_read_only(path)?;
+    read_metadata(&conn)
+}
+
+/// Reads metadata from the given database, failing with [`MalformedMetadata`] if `bounds` or `center` is present but
+/// malformed.
+pub fn read_metadata_strict(conn: &impl AsConnection) -> Result<Metadata, Box<dyn Error>> {
+    let conn = conn.as_connection();
+    Ok(read_metadata_impl(conn, "metadata", "tiles", true)?.0)
+}
+
+/// Reads metadata from the given database along with the problems found while reading it: duplicate keys, malformed
+/// values of known keys (which are ignored like in [`read_metadata`]) and unknown keys (which are stored in
+/// [`Metadata::custom`]).
+pub fn read_metadata_verbose(conn: &impl AsConnection) -> Result<(Metadata, Vec<MetadataWarning>), Box<dyn Error>> {
+    let conn = conn.as_connection();
+    read_metadata_impl(conn, "metadata", "tiles", false)
+}
+
+fn read_metadata_impl(
+    conn: &rusqlite::Connection,
+    table: &str,
+    tiles_table: &str,
+    strict: bool,
+) -> Result<(Metadata, Vec<MetadataWarning>), Box<dyn Error>> {
+    let mut select_metadata = conn.prepare_cached(&format!("SELECT name, value FROM {}", table))?;
     let mut rows = select_metadata.query([])?;
 
     let mut metadata = Metadata::default();
+    let mut warnings = Vec::new();
+    let mut keys = HashSet::new();
 
-    let mut zoom_range = (None, None);
     let mut format_str = String::new();
-    let mut mvt_metadata_json = String::new();
+    let mut json = None;
+    let mut custom_json = None;
 
     while let Some(row) = rows.next()? {
         let name: String = row.get(0)?;
         let value: String = row.get(1)?;
 
+        if !keys.insert(name.clone()) {
+            warnings.push(MetadataWarning::DuplicateKey(name.clone()));
+        }
+
         match name.as_str() {
             "name" => metadata.name = value,
             "format" => format_str = value,
-            "bounds" => {
-                let split: Vec<&str> = value.split(",").collect();
-                if split.len() == 4 {
-                    let bounds = (
-                        split[0].parse::<f64>(),
-                        split[1].parse::<f64>(),
-                        split[2].parse::<f64>(),
-                        split[3].parse::<f64>(),
-                    );
-                    if let (Ok(left), Ok(bottom), Ok(right), Ok(top)) = bounds {
-                        let tl_br = (GeoCoord::from_degrees(left, top), GeoCoord::from_degrees(right, bottom));
-                        if let (Ok(tl), Ok(br)) = tl_br {
-                            if let Ok(bbox) = GeoRect::new(tl, br) {
-                                metadata.bounds = Some(bbox);
-                            }
-                        }
-                    }
-                }
-            }
-            "center" => {
-                let split: Vec<&str> = value.split(",").collect();
-                if split.len() == 3 {
-                    let center = (
-                        split[0].parse::<f64>(),
-                        split[1].parse::<f64>(),
-                        split[2].parse::<u32>(),
-                    );
-                    if let (Ok(lon), Ok(lat), Ok(zoom_level)) = center {
-                        if let Ok(coord) = GeoCoord::from_degrees(lon, lat) {
-                            metadata.center = Some((coord, zoom_level));
-                        }
-                    }
-                }
-            }
-            "minzoom" => {
-                if let Ok(minzoom) = value.parse::<u32>() {
-                    zoom_range.0 = Some(minzoom);
-                }
-            }
-            "maxzoom" => {
-                if let Ok(maxzoom) = value.parse::<u32>() {
-                    zoom_range.1 = Some(maxzoom);
-                }
-            }
+            "bounds" => match parse_bounds(&value) {
+                Ok(ParsedBounds::Regular(bounds)) => metadata.bounds = Some(bounds),
+                Ok(ParsedBounds::Antimeridian(west, east)) => metadata.antimeridian_bounds = Some((west, east)),
+                Err(reason) => malformed_value(&mut warnings, strict, &name, value, reason)?,
+            },
+            "center" => match parse_center(&value) {
+                Ok(center) => metadata.center = Some(center),
+                Err(reason) => malformed_value(&mut warnings, strict, &name, value, reason)?,
+            },
+            "minzoom" => match value.parse::<u32>() {
+                Ok(minzoom) => metadata.minzoom = Some(minzoom),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "invalid zoom level")?,
+            },
+            "maxzoom" => match value.parse::<u32>() {
+                Ok(maxzoom) => metadata.maxzoom = Some(maxzoom),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "invalid zoom level")?,
+            },
             "attribution" => metadata.attribution = Some(value),
             "description" => metadata.description = Some(value),
-            "type" => {
-                if let Ok(r#type) = Type::try_from(value.as_str()) {
-                    metadata.r#type = Some(r#type);
+            "type" => match Type::try_from(value.as_str()) {
+                Ok(r#type) => metadata.r#type = Some(r#type),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "unknown type")?,
+            },
+            "version" => match value.parse::<u32>() {
+                Ok(version) => metadata.version = Some(version),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "invalid version")?,
+            },
+            "generator" => metadata.generator = Some(value),
+            "generator_options" => metadata.generator_options = Some(value),
+            "scheme" => match Scheme::try_from(value.as_str()) {
+                Ok(scheme) => metadata.scheme = Some(scheme),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "unknown scheme")?,
+            },
+            "compression" => match Compression::try_from(value.as_str()) {
+                Ok(compression) => metadata.compression = Some(compression),
+                Err(_) => malformed_value(&mut warnings, false, &name, value, "unknown compression")?,
+            },
+            "json" => json = Some(value),
+            "custom_json" => match serde_json::from_str::<HashMap<String, String>>(&value) {
+                Ok(custom) => custom_json = Some(custom),
+                Err(_) => {
+                    // Kept as a regular custom row, so that the value isn't lost when written back
+                    malformed_value(
+                        &mut warnings,
+                        false,
+                        &name,
+                        value.clone(),
+                        "invalid JSON object of strings",
+                    )?;
+                    metadata.custom.insert(name, value);
                 }
-            }
-            "version" => {
-                if let Ok(version) = value.parse::<u32>() {
-                    metadata.version = Some(version);
-                }
-            }
-            "json" => mvt_metadata_json = value,
+            },
             unknown_key => {
+                warnings.push(MetadataWarning::UnknownKey(unknown_key.to_owned()));
                 metadata.custom.insert(unknown_key.to_owned(), value);
             }
         }
@@ -95,7 +237,7 @@ pub fn read_metadata(conn: &rusqlite::Connection) -> Result<Metadata, Box<dyn st
 
     metadata.format = match format_str.as_str() {
         "pbf" => {
-            let mvt_metadata = serde_json::from_str::<MvtMetadata>(&mvt_metadata_json)?;
+            let mvt_metadata = serde_json::from_str::<MvtMetadata>(json.as_deref().unwrap_or_default())?;
             FileFormat::Pbf(mvt_metadata)
         }
         "jpg" => FileFormat::Jpg,
@@ -104,88 +246,2218 @@ pub fn read_metadata(conn: &rusqlite::Connection) -> Result<Metadata, Box<dyn st
         ietf_type => FileFormat::Other(ietf_type.to_owned()),
     };
 
-    if let (Some(minzoom), Some(maxzoom)) = zoom_range {
-        metadata.zoom_range = Some(minzoom..=maxzoom);
+    if let FileFormat::Jpg | FileFormat::Png | FileFormat::Webp = metadata.format {
+        metadata.raster_json = json.as_deref().and_then(RasterJson::parse);
+    }
+    metadata.raw_json = json;
+
+    for (key, value) in custom_json.unwrap_or_default() {
+        metadata.custom.entry(key).or_insert(value);
+    }
+
+    if metadata.compression.is_none() {
+        // Best effort, e.g. the tiles table may not exist yet
+        let sql = format!("SELECT tile_data FROM {} LIMIT 1", tiles_table);
+        metadata.inferred_compression = conn
+            .query_row(&sql, [], |row| row.get::<_, Vec<u8>>(0))
+            .ok()
+            .and_then(|tile_data| recognize_compression(&tile_data));
+    }
+
+    Ok((metadata, warnings))
+}
+
+/// Records a malformed metadata value as a warning, or fails with [`MalformedMetadata`] if `strict`.
+fn malformed_value(
+    warnings: &mut Vec<MetadataWarning>,
+    strict: bool,
+    key: &str,
+    value: String,
+    reason: &'static str,
+) -> Result<(), Box<dyn Error>> {
+    if strict {
+        return Err(Box::new(MalformedMetadata::new(key, value, reason)));
+    }
+    warnings.push(MetadataWarning::MalformedValue {
+        key: key.to_owned(),
+        value,
+        reason,
+    });
+    Ok(())
+}
+
+/// Reads all rows of the `metadata` table as they are stored, in table order and including duplicate names.
+pub fn read_metadata_raw(conn: &impl AsConnection) -> rusqlite::Result<Vec<(String, String)>> {
+    let conn = conn.as_connection();
+    let mut select_metadata = conn.prepare_cached("SELECT name, value FROM metadata ORDER BY rowid")?;
+    let rows = select_metadata.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Reads the metadata and writes it as a pretty-printed [TileJSON](https://github.com/mapbox/tilejson-spec) file to
+/// the given path, see [`Metadata::to_tilejson`].
+pub fn write_tilejson<P: AsRef<Path>>(
+    conn: &impl AsConnection,
+    path: P,
+    tile_url_template: &str,
+) -> Result<(), Box<dyn Error>> {
+    let tilejson = read_metadata(conn)?.to_tilejson(tile_url_template)?;
+    std::fs::write(path, serde_json::to_string_pretty(&tilejson)?)?;
+    Ok(())
+}
+
+/// Reads the value of a single metadata row with the given name.
+///
+/// If the row is not found, `None` is returned.
+pub fn get_metadata_value(conn: &impl AsConnection, name: &str) -> rusqlite::Result<Option<String>> {
+    let conn = conn.as_connection();
+    select_first(conn, "SELECT value FROM metadata WHERE name = ?1", params![name])
+}
+
+/// Reads the vector tile metadata from the `json` metadata row, without reading the rest of the metadata.
+///
+/// If there's no `json` row, `None` is returned. Invalid JSON results in a
+/// [`FromSqlConversionFailure`](rusqlite::Error::FromSqlConversionFailure) error.
+pub fn read_mvt_metadata(conn: &impl AsConnection) -> rusqlite::Result<Option<MvtMetadata>> {
+    get_metadata_value(conn, "json")?
+        .map(|json| {
+            serde_json::from_str(&json)
+                .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))
+        })
+        .transpose()
+}
+
+/// Reads the vector layers from the `json` metadata row, skipping the potentially large `tilestats` object.
+///
+/// The `tilestats` of the returned metadata is always `None`, use [`read_tilestats`] to read it separately when needed.
+/// If there's no `json` row, an empty list of vector layers is returned.
+pub fn read_mvt_metadata_lazy(conn: &impl AsConnection) -> Result<MvtMetadata, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct VectorLayers {
+        vector_layers: Vec<VectorLayer>,
+    }
+
+    let vector_layers = match get_metadata_value(conn, "json")? {
+        Some(json) => serde_json::from_str::<VectorLayers>(&json)?.vector_layers,
+        None => Vec::new(),
+    };
+
+    Ok(MvtMetadata {
+        vector_layers,
+        tilestats: None,
+    })
+}
+
+/// Reads only the `tilestats` object from the `json` metadata row, see [`read_mvt_metadata_lazy`].
+///
+/// If there's no `json` row or it has no `tilestats`, `None` is returned.
+pub fn read_tilestats(conn: &impl AsConnection) -> Result<Option<Tilestats>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct TilestatsOnly {
+        tilestats: Option<Tilestats>,
+    }
+
+    match get_metadata_value(conn, "json")? {
+        Some(json) => Ok(serde_json::from_str::<TilestatsOnly>(&json)?.tilestats),
+        None => Ok(None),
+    }
+}
+
+/// Bounds parsed from the `bounds` metadata row.
+enum ParsedBounds {
+    Regular(GeoRect),
+    /// Bounds crossing the antimeridian, split into the parts west and east of it.
+    Antimeridian(GeoRect, GeoRect),
+}
+
+/// Parses a `bounds` value in the `left,bottom,right,top` format.
+///
+/// Whitespace around the numbers is tolerated. If `left` is greater than `right`, the bounds cross the antimeridian
+/// and are split into the parts west (`left` to 180) and east (-180 to `right`) of it.
+fn parse_bounds(value: &str) -> Result<ParsedBounds, &'static str> {
+    let split: Vec<&str> = value.split(',').map(str::trim).collect();
+    if split.len() != 4 {
+        return Err("expected 4 comma-separated numbers");
+    }
+
+    let mut numbers = [0.0; 4];
+    for (number, s) in numbers.iter_mut().zip(&split) {
+        *number = s.parse::<f64>().map_err(|_| "invalid number")?;
+    }
+    let [left, bottom, right, top] = numbers;
+
+    let rect = |left: f64, right: f64| {
+        let tl = GeoCoord::from_degrees(left, top).map_err(|_| "coordinate out of range")?;
+        let br = GeoCoord::from_degrees(right, bottom).map_err(|_| "coordinate out of range")?;
+        GeoRect::new(tl, br).map_err(|_| "corners are out of order")
+    };
+
+    if left > right {
+        Ok(ParsedBounds::Antimeridian(rect(left, 180.0)?, rect(-180.0, right)?))
+    } else {
+        rect(left, right).map(ParsedBounds::Regular)
+    }
+}
+
+/// Parses a `center` value in the `longitude,latitude,zoom` format.
+///
+/// Whitespace around the numbers is tolerated, and an integral zoom level might be written as a float (e.g. `4.0`).
+fn parse_center(value: &str) -> Result<(GeoCoord, u32), &'static str> {
+    let split: Vec<&str> = value.split(',').map(str::trim).collect();
+    if split.len() != 3 {
+        return Err("expected 3 comma-separated numbers");
     }
 
-    Ok(metadata)
+    let lon = split[0].parse::<f64>().map_err(|_| "invalid longitude")?;
+    let lat = split[1].parse::<f64>().map_err(|_| "invalid latitude")?;
+    let zoom_level = match split[2].parse::<u32>() {
+        Ok(zoom_level) => zoom_level,
+        Err(_) => match split[2].parse::<f64>() {
+            Ok(zoom_level) if zoom_level >= 0.0 && zoom_level.fract() == 0.0 && zoom_level <= u32::MAX as f64 => {
+                zoom_level as u32
+            }
+            _ => return Err("invalid zoom level"),
+        },
+    };
+
+    let coord = GeoCoord::from_degrees(lon, lat).map_err(|_| "coordinate out of range")?;
+
+    Ok((coord, zoom_level))
 }
 
 /// Reads the given tile from the database.
 ///
-/// If the tile is not found, `None` is returned.
-pub fn read_tile(conn: &rusqlite::Connection, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+/// If the tile is not found, `None` is returned. References to the canonical empty tile are resolved, see
+/// [`write_empty_tile_ref`](crate::write::write_empty_tile_ref).
+///
+/// **Note:** the empty tile is looked up for every zero-length tile, see [`TileQuery`] for a variant which does so
+/// once.
+pub fn read_tile(conn: &impl AsConnection, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+    let conn = conn.as_connection();
+    let tile_data: Option<Vec<u8>> = select_first(
+        conn,
+        "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+        params![tile_id.z(), tile_id.x(), tile_id.y()],
+    )?;
+
+    let mut empty_tile = EmptyTile::default();
+    tile_data
+        .map(|tile_data| empty_tile.resolve(conn, tile_data))
+        .transpose()
+}
+
+/// Reads the given tile and decompresses it with the given compression, see [`read_tile`].
+#[cfg(feature = "compression")]
+pub fn read_tile_decompressed(
+    conn: &impl AsConnection,
+    tile_id: TmsTileId,
+    compression: Compression,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    match read_tile(conn, tile_id)? {
+        Some(tile_data) => Ok(Some(compression.decompress(&tile_data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the given tile along with its media type, e.g. for serving it over HTTP.
+///
+/// The media type is looked up from the `format` metadata row, or guessed from the tile data with
+/// [`detect_media_type`] if the format is absent or not one of the formats of the MBTiles specification.
+pub fn read_tile_typed(
+    conn: &impl AsConnection,
+    tile_id: TmsTileId,
+) -> rusqlite::Result<Option<(Vec<u8>, &'static str)>> {
+    let tile_data = match read_tile(conn, tile_id)? {
+        Some(tile_data) => tile_data,
+        None => return Ok(None),
+    };
+
+    let media_type = match get_metadata_value(conn, "format")?.as_deref() {
+        Some("pbf") => "application/x-protobuf",
+        Some("jpg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => detect_media_type(&tile_data),
+    };
+
+    Ok(Some((tile_data, media_type)))
+}
+
+/// Reads the given tile into the given buffer, replacing its contents, and returns whether the tile was found.
+///
+/// Unlike [`read_tile`], this doesn't allocate when the buffer is already large enough, so a buffer can be reused to
+/// read many tiles. References to the canonical empty tile are resolved like in [`read_tile`].
+pub fn read_tile_into(conn: &impl AsConnection, tile_id: TmsTileId, buf: &mut Vec<u8>) -> rusqlite::Result<bool> {
+    let conn = conn.as_connection();
     let mut select_tile = conn
         .prepare_cached("SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
     let mut rows = select_tile.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
 
+    buf.clear();
+
     if let Some(row) = rows.next()? {
-        let tile_data: Vec<u8> = row.get(0)?;
-        Ok(Some(tile_data))
+        match row.get_ref(0)? {
+            ValueRef::Blob(tile_data) if tile_data.is_empty() => {
+                if let Some(empty_tile) = EmptyTile::default().get(conn)? {
+                    buf.extend_from_slice(empty_tile);
+                }
+            }
+            ValueRef::Blob(tile_data) => buf.extend_from_slice(tile_data),
+            value => {
+                return Err(rusqlite::Error::InvalidColumnType(
+                    0,
+                    "tile_data".to_owned(),
+                    value.data_type(),
+                ))
+            }
+        }
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Reads the given tile along with its `created_at` timestamp, for databases whose `tiles` table has such a column.
+///
+/// If the tile is not found, `None` is returned. The timestamp is `None` if the column doesn't exist or is `NULL`.
+///
+/// **Note:** the column is looked up on every call, see [`TileQuery::get_with_meta`] for a variant which does so once.
+pub fn read_tile_with_meta(
+    conn: &impl AsConnection,
+    tile_id: TmsTileId,
+) -> rusqlite::Result<Option<(Vec<u8>, Option<i64>)>> {
+    let conn = conn.as_connection();
+    if !has_column(conn, "tiles", "created_at")? {
+        return Ok(read_tile(conn, tile_id)?.map(|tile_data| (tile_data, None)));
+    }
+
+    let mut select_tile = conn.prepare_cached(
+        "SELECT tile_data, created_at FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+    )?;
+    let mut rows = select_tile.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+
+    if let Some(row) = rows.next()? {
+        let tile_data = EmptyTile::default().resolve(conn, row.get(0)?)?;
+        Ok(Some((tile_data, row.get(1)?)))
     } else {
         Ok(None)
     }
 }
 
-/// Reads the given grid from the database.
+/// Checks whether the given table has a column with the given name.
+fn has_column(conn: &rusqlite::Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut select_column = conn.prepare_cached("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?;
+    select_column.exists(params![table, column])
+}
+
+/// Reads the given tile into a shared slice, copying the data directly from SQLite, e.g. for storing it in a cache.
 ///
-/// If the grid is not found, `None` is returned.
-pub fn read_grid(conn: &rusqlite::Connection, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
-    let mut select_grid =
-        conn.prepare_cached("SELECT grid FROM grids WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
-    let mut rows = select_grid.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+/// If the tile is not found, `None` is returned. References to the canonical empty tile are resolved like in
+/// [`read_tile`].
+pub fn read_tile_arc(conn: &impl AsConnection, tile_id: TmsTileId) -> rusqlite::Result<Option<Arc<[u8]>>> {
+    let conn = conn.as_connection();
+    let mut select_tile = conn
+        .prepare_cached("SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
+    let mut rows = select_tile.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
 
     if let Some(row) = rows.next()? {
-        let grid: Vec<u8> = row.get(0)?;
-        Ok(Some(grid))
+        match row.get_ref(0)? {
+            ValueRef::Blob(tile_data) => {
+                let mut empty_tile = EmptyTile::default();
+                let tile_data = if tile_data.is_empty() {
+                    empty_tile.get(conn)?.unwrap_or(tile_data)
+                } else {
+                    tile_data
+                };
+                Ok(Some(Arc::from(tile_data)))
+            }
+            value => Err(rusqlite::Error::InvalidColumnType(
+                0,
+                "tile_data".to_owned(),
+                value.data_type(),
+            )),
+        }
     } else {
         Ok(None)
     }
 }
 
-/// Reads the grid data for the given key from the database.
+/// A tile lookup with an already prepared statement.
 ///
-/// If the grid data is not found, `None` is returned.
-pub fn read_grid_data(conn: &rusqlite::Connection, tile_id: TmsTileId, key: &str) -> rusqlite::Result<Option<String>> {
-    let mut select_grid = conn.prepare_cached(
-        "SELECT key_json FROM grid_data WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3 AND key = ?4",
+/// Unlike [`read_tile`], this doesn't look up the statement in the connection's statement cache on every call, which
+/// matters in hot loops like serving tiles.
+pub struct TileQuery<'conn> {
+    conn: &'conn rusqlite::Connection,
+    select_tile: rusqlite::Statement<'conn>,
+    has_created_at: bool,
+    empty_tile: EmptyTile,
+}
+
+impl<'conn> TileQuery<'conn> {
+    /// Prepares the tile lookup for the given connection.
+    pub fn new<C: AsConnection>(conn: &'conn C) -> rusqlite::Result<Self> {
+        let conn = conn.as_connection();
+        let has_created_at = has_column(conn, "tiles", "created_at")?;
+        let sql = format!(
+            "SELECT tile_data{} FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            if has_created_at { ", created_at" } else { "" }
+        );
+        let select_tile = conn.prepare(&sql)?;
+        Ok(TileQuery {
+            conn,
+            select_tile,
+            has_created_at,
+            empty_tile: EmptyTile::default(),
+        })
+    }
+
+    /// Reads the given tile along with its `created_at` timestamp, see [`read_tile_with_meta`].
+    ///
+    /// Whether the column exists is only checked once when the lookup is prepared.
+    pub fn get_with_meta(&mut self, tile_id: TmsTileId) -> rusqlite::Result<Option<(Vec<u8>, Option<i64>)>> {
+        let mut rows = self.select_tile.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+
+        if let Some(row) = rows.next()? {
+            let created_at = if self.has_created_at { row.get(1)? } else { None };
+            let tile_data = self.empty_tile.resolve(self.conn, row.get(0)?)?;
+            Ok(Some((tile_data, created_at)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the given tile from the database.
+    ///
+    /// If the tile is not found, `None` is returned. References to the canonical empty tile are resolved like in
+    /// [`read_tile`], but the empty tile is only looked up once.
+    pub fn get(&mut self, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+        let mut rows = self.select_tile.query(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(self.empty_tile.resolve(self.conn, row.get(0)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Reads the tile with the given XYZ scheme coordinates from the database, see [`read_tile`].
+///
+/// Coordinates out of range for the zoom level result in a
+/// [`ToSqlConversionFailure`](rusqlite::Error::ToSqlConversionFailure) error with an
+/// [`InvalidTileCoord`](crate::common::InvalidTileCoord) cause.
+pub fn read_tile_xyz(conn: &impl AsConnection, z: u32, x: u32, y: u32) -> rusqlite::Result<Option<Vec<u8>>> {
+    read_tile(conn, xyz_param(z, x, y)?)
+}
+
+/// Returns the SQLite `rowid` of the given tile, which can be used for faster lookups with [`read_tile_by_rowid`].
+///
+/// If the tile is not found, `None` is returned.
+///
+/// **Note:** the deduplicating layout stores `tiles` as a view, which has no `rowid`. Row IDs might also change when
+/// the database is vacuumed, unless the `tiles` table has an explicit `INTEGER PRIMARY KEY` column.
+pub fn tile_rowid(conn: &impl AsConnection, tile_id: TmsTileId) -> rusqlite::Result<Option<i64>> {
+    let conn = conn.as_connection();
+    select_first(
+        conn,
+        "SELECT rowid FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+        params![tile_id.z(), tile_id.x(), tile_id.y()],
+    )
+}
+
+/// Reads the tile with the given SQLite `rowid` from the database, see [`tile_rowid`].
+///
+/// If the tile is not found, `None` is returned.
+pub fn read_tile_by_rowid(conn: &impl AsConnection, rowid: i64) -> rusqlite::Result<Option<Vec<u8>>> {
+    let conn = conn.as_connection();
+    let tile_data: Option<Vec<u8>> =
+        select_first(conn, "SELECT tile_data FROM tiles WHERE rowid = ?1", params![rowid])?;
+
+    let mut empty_tile = EmptyTile::default();
+    tile_data
+        .map(|tile_data| empty_tile.resolve(conn, tile_data))
+        .transpose()
+}
+
+/// Returns a version token of the given tile, which changes whenever the tile data changes, e.g. for use as an HTTP
+/// `ETag`.
+///
+/// For databases using the deduplicating `map`/`images` layout, the token is derived from the image ID stored in
+/// `map`, without reading the tile data. Otherwise it's a 64-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/)
+/// hash of the tile data. If the tile is not found, `None` is returned.
+pub fn tile_version(conn: &impl AsConnection, tile_id: TmsTileId) -> rusqlite::Result<Option<u64>> {
+    let conn = conn.as_connection();
+
+    if table_exists(conn, "map")? {
+        let image_id: Option<String> = select_first(
+            conn,
+            "SELECT CAST(tile_id AS TEXT) FROM map WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            params![tile_id.z(), tile_id.x(), tile_id.y()],
+        )?;
+        Ok(image_id.map(|image_id| fnv1a(image_id.as_bytes())))
+    } else {
+        let mut tile_data = Vec::new();
+        let found = read_tile_into(conn, tile_id, &mut tile_data)?;
+        Ok(if found { Some(fnv1a(&tile_data)) } else { None })
+    }
+}
+
+/// Reads the tile at the lowest zoom level in the database, e.g. for use as a thumbnail of the tileset.
+///
+/// For complete tilesets this is the single `0/0/0` tile. If the lowest zoom level has multiple tiles, the one with
+/// the lowest column and row is returned. If there are no tiles, `None` is returned.
+pub fn read_overview_tile(conn: &impl AsConnection) -> rusqlite::Result<Option<(TmsTileId, Vec<u8>)>> {
+    let conn = conn.as_connection();
+    let mut select_tile = conn.prepare_cached(
+        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles
+        ORDER BY zoom_level, tile_column, tile_row LIMIT 1",
     )?;
-    let mut rows = select_grid.query(params![tile_id.z(), tile_id.x(), tile_id.y(), key])?;
+    let mut rows = select_tile.query([])?;
 
     if let Some(row) = rows.next()? {
-        let grid_data: String = row.get(0)?;
-        Ok(Some(grid_data))
+        Ok(Some((
+            read_tile_id(row)?,
+            EmptyTile::default().resolve(conn, row.get(3)?)?,
+        )))
     } else {
         Ok(None)
     }
 }
 
-#[cfg(test)]
-mod mbtiles_read_test {
-    use super::*;
+/// Reads the details of a single raster tile from its image header, e.g. to tell indexed PNG tiles from truecolor
+/// ones, see [`image_details`].
+///
+/// The [overview tile](read_overview_tile) is sampled, assuming all tiles are encoded alike. If there are no tiles or
+/// the sampled tile isn't a PNG, JPEG or WebP image, `None` is returned.
+pub fn sample_tile_format_details(conn: &impl AsConnection) -> rusqlite::Result<Option<ImageDetails>> {
+    Ok(read_overview_tile(conn)?.and_then(|(_, tile_data)| image_details(&tile_data)))
+}
 
-    #[test]
-    fn read_mvt_metadata() {
-        let json = r#"{
-            "vector_layers": [
-                {
-                    "id": "tl_2016_us_county",
-                    "description": "Census counties",
-                    "minzoom": 0,
-                    "maxzoom": 5,
-                    "fields": {
-                        "ALAND": "Number",
-                        "AWATER": "Number",
-                        "GEOID": "String",
-                        "MTFCC": "String",
-                        "NAME": "String"
-                    }
+/// Reads the overview tile like [`read_overview_tile`], decompressing it if it's GZIP-compressed, as vector tiles
+/// usually are.
+#[cfg(feature = "compression")]
+pub fn read_overview_tile_decompressed(
+    conn: &impl AsConnection,
+) -> Result<Option<(TmsTileId, Vec<u8>)>, Box<dyn Error>> {
+    match read_overview_tile(conn)? {
+        Some((tile_id, tile_data)) if detect_compression(&tile_data) == Compression::Gzip => {
+            Ok(Some((tile_id, gunzip(&tile_data)?)))
+        }
+        tile => Ok(tile),
+    }
+}
+
+/// Reads the given tiles from the database, using a single query per 256 tiles.
+///
+/// The returned tiles are in the same order as `tile_ids`, with `None` for tiles which are not found.
+pub fn read_tiles(
+    conn: &impl AsConnection,
+    tile_ids: &[TmsTileId],
+) -> rusqlite::Result<Vec<(TmsTileId, Option<Vec<u8>>)>> {
+    let conn = conn.as_connection();
+    const TILES_PER_QUERY: usize = 256;
+
+    let mut tiles: Vec<(TmsTileId, Option<Vec<u8>>)> = tile_ids.iter().map(|&tile_id| (tile_id, None)).collect();
+
+    let mut positions: HashMap<(u32, u32, u32), Vec<usize>> = HashMap::new();
+    for (i, tile_id) in tile_ids.iter().enumerate() {
+        positions
+            .entry((tile_id.z(), tile_id.x(), tile_id.y()))
+            .or_default()
+            .push(i);
+    }
+
+    let mut empty_tile = EmptyTile::default();
+
+    for chunk in tile_ids.chunks(TILES_PER_QUERY) {
+        let sql = format!(
+            "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles \
+            WHERE (zoom_level, tile_column, tile_row) IN (VALUES {})",
+            vec!["(?, ?, ?)"; chunk.len()].join(", ")
+        );
+        let mut select_tiles = conn.prepare_cached(&sql)?;
+
+        let coords = chunk
+            .iter()
+            .flat_map(|tile_id| vec![tile_id.z(), tile_id.x(), tile_id.y()]);
+        let mut rows = select_tiles.query(params_from_iter(coords))?;
+
+        while let Some(row) = rows.next()? {
+            let coord: (u32, u32, u32) = (row.get(0)?, row.get(1)?, row.get(2)?);
+            let tile_data = empty_tile.resolve(conn, row.get(3)?)?;
+
+            if let Some((&last, rest)) = positions.get(&coord).and_then(|indices| indices.split_last()) {
+                for &i in rest {
+                    tiles[i].1 = Some(tile_data.clone());
                 }
-            ]
-        }"#;
+                tiles[last].1 = Some(tile_data);
+            }
+        }
+    }
 
-        let mvt_json = serde_json::from_str::<MvtMetadata>(json);
+    Ok(tiles)
+}
 
-        assert!(mvt_json.is_ok());
+/// Reads all tiles at the given zoom level which intersect the given rectangle.
+pub fn read_tiles_in_rect(
+    conn: &impl AsConnection,
+    rect: &GeoRect,
+    zoom: u32,
+) -> rusqlite::Result<Vec<(TmsTileId, Vec<u8>)>> {
+    let conn = conn.as_connection();
+    let (columns, rows) = rect_to_tile_range(rect, zoom);
+    read_tile_block(conn, to_zoom_level(zoom)?, columns, rows)
+}
+
+/// Reads all tiles at the given zoom level which intersect the polygon given by its vertices.
+///
+/// Candidate tiles are queried by the bounding box of the polygon, then kept if their extent overlaps the polygon, so
+/// a polygon within a single tile returns that tile. Tiles are returned whole, and tiles which only touch the polygon
+/// at their edges may be left out.
+pub fn read_tiles_in_polygon(
+    conn: &impl AsConnection,
+    polygon: &[GeoCoord],
+    zoom: u32,
+) -> rusqlite::Result<Vec<(TmsTileId, Vec<u8>)>> {
+    let conn = conn.as_connection();
+    if polygon.len() < 3 {
+        return Ok(Vec::new());
+    }
+
+    let vertices: Vec<(f64, f64)> = polygon.iter().map(|coord| (coord.lon(), coord.lat())).collect();
+
+    let (mut west, mut south, mut east, mut north) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for &(lon, lat) in &vertices {
+        west = west.min(lon);
+        south = south.min(lat);
+        east = east.max(lon);
+        north = north.max(lat);
+    }
+
+    let (columns, rows) = extent_to_tile_range(west, south, east, north, zoom);
+    let mut tiles = read_tile_block(conn, to_zoom_level(zoom)?, columns, rows)?;
+
+    tiles.retain(|(tile_id, _)| {
+        polygon_intersects_extent(&vertices, tile_extent(tile_id.z(), tile_id.x(), tile_id.y()))
+    });
+
+    Ok(tiles)
+}
+
+/// Validates a zoom level given as a number, failing with a
+/// [`ToSqlConversionFailure`](rusqlite::Error::ToSqlConversionFailure) error.
+fn to_zoom_level(zoom: u32) -> rusqlite::Result<ZoomLevel> {
+    ZoomLevel::try_from(zoom).map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+/// Reads all tiles at the given zoom level within the given block of TMS columns and rows, using a single query.
+///
+/// Unlike [`read_tiles_in_rect`], the block is given in tile coordinates instead of geographic ones.
+pub fn read_tile_block(
+    conn: &impl AsConnection,
+    zoom: ZoomLevel,
+    columns: RangeInclusive<u32>,
+    rows: RangeInclusive<u32>,
+) -> rusqlite::Result<Vec<(TmsTileId, Vec<u8>)>> {
+    let conn = conn.as_connection();
+    let mut select_tiles = conn.prepare_cached(
+        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles \
+        WHERE zoom_level = ?1 AND tile_column BETWEEN ?2 AND ?3 AND tile_row BETWEEN ?4 AND ?5",
+    )?;
+    let mut empty_tile = EmptyTile::default();
+    let tiles = select_tiles.query_map(
+        params![zoom.get(), columns.start(), columns.end(), rows.start(), rows.end()],
+        |row| Ok((read_tile_id(row)?, empty_tile.resolve(conn, row.get(3)?)?)),
+    )?;
+    tiles.collect()
+}
+
+/// Reads all tiles matching the given SQL predicate, ordered by zoom level, column and row, as an escape hatch for
+/// queries not covered by the other functions.
+///
+/// The predicate is appended as the `WHERE` clause of a query of the `tiles` table, e.g.
+/// `LENGTH(tile_data) > ?1`, with `params` bound to its parameters. Scalar functions registered on the connection
+/// with `create_scalar_function` can be used in it.
+///
+/// **Note:** the predicate is inserted into the query verbatim, so it must never contain untrusted input, which
+/// belongs into `params` instead.
+pub fn read_tiles_where<P: Params>(
+    conn: &impl AsConnection,
+    sql_predicate: &str,
+    params: P,
+) -> rusqlite::Result<Vec<(TmsTileId, Vec<u8>)>> {
+    let conn = conn.as_connection();
+    let sql = format!(
+        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles WHERE ({})
+        ORDER BY zoom_level, tile_column, tile_row",
+        sql_predicate
+    );
+    let mut select_tiles = conn.prepare(&sql)?;
+    let mut empty_tile = EmptyTile::default();
+    let tiles = select_tiles.query_map(params, |row| {
+        Ok((read_tile_id(row)?, empty_tile.resolve(conn, row.get(3)?)?))
+    })?;
+    tiles.collect()
+}
+
+/// The framing of the tiles written by [`write_tiles_to`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Each tile is written as its zoom level, column, TMS row and data length, each as a big-endian `u32`, followed
+    /// by its data.
+    LengthPrefixed,
+    /// Each tile is written as an entry of a [tar](https://www.gnu.org/software/tar/manual/html_node/Standard.html)
+    /// archive named `{z}/{x}/{y}` with the TMS row, followed by the end-of-archive marker.
+    Tar,
+}
+
+/// Writes all tiles in the database into the given sink with the given framing, e.g. to archive them without
+/// intermediate files, returning the number of written tiles.
+///
+/// Tiles are written in the order of [`tiles`], so only a batch of them is held in memory.
+pub fn write_tiles_to<W: Write>(
+    conn: &impl AsConnection,
+    sink: &mut W,
+    framing: Framing,
+) -> Result<u64, Box<dyn Error>> {
+    let mut written = 0;
+
+    for tile in tiles(conn) {
+        let (tile_id, tile_data) = tile?;
+        match framing {
+            Framing::LengthPrefixed => {
+                let len =
+                    u32::try_from(tile_data.len()).map_err(|_| "tile data too long for length-prefixed framing")?;
+                for value in [tile_id.z(), tile_id.x(), tile_id.y(), len] {
+                    sink.write_all(&value.to_be_bytes())?;
+                }
+                sink.write_all(&tile_data)?;
+            }
+            Framing::Tar => {
+                let name = format!("{}/{}/{}", tile_id.z(), tile_id.x(), tile_id.y());
+                sink.write_all(&tar_header(&name, tile_data.len() as u64))?;
+                sink.write_all(&tile_data)?;
+                let padding = (512 - tile_data.len() % 512) % 512;
+                sink.write_all(&[0; 512][..padding])?;
+            }
+        }
+        written += 1;
+    }
+
+    if framing == Framing::Tar {
+        sink.write_all(&[0; 1024])?;
+    }
+
+    Ok(written)
+}
+
+/// Builds the ustar header of a regular file entry with the given name and size.
+fn tar_header(name: &str, size: u64) -> [u8; 512] {
+    let mut header = [0; 512];
+
+    let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    header
+}
+
+/// The order in which [`tiles_ordered`] returns the tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    /// The order of the SQLite `rowid`, which is usually the order the tiles were written in.
+    RowId,
+    /// Ordered by zoom level, column and row, like [`tiles`].
+    Zxy,
+    /// Ordered by zoom level, then along a [Hilbert curve](https://en.wikipedia.org/wiki/Hilbert_curve), so tiles
+    /// close to each other on the map are also close to each other in the order.
+    Hilbert,
+}
+
+/// Returns an iterator over all tiles in the database in the given order, e.g. to repack them with spatial locality.
+///
+/// Like [`tiles`], this doesn't keep a statement open between iterations.
+///
+/// **Note:** the [`Hilbert`](TileOrder::Hilbert) order is sorted in memory one zoom level at a time, holding the
+/// coordinates (but not the data) of all tiles of a zoom level, which takes about 24 bytes per tile. The data of each
+/// tile is then looked up separately, which is only fast with the [`tile_index`](crate::write::create_tile_index).
+/// The deduplicating layout stores `tiles` as a view, which has no `rowid`.
+pub fn tiles_ordered(
+    conn: &impl AsConnection,
+    order: TileOrder,
+) -> Box<dyn Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_> {
+    match order {
+        TileOrder::RowId => Box::new(tiles_by_rowid(conn.as_connection())),
+        TileOrder::Zxy => Box::new(tiles(conn)),
+        TileOrder::Hilbert => Box::new(tiles_by_hilbert_index(conn.as_connection())),
+    }
+}
+
+fn tiles_by_rowid(conn: &rusqlite::Connection) -> impl Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_ {
+    let mut empty_tile = EmptyTile::default();
+    let mut fetch = move |last: i64| -> rusqlite::Result<Vec<(i64, TmsTileId, Vec<u8>)>> {
+        let mut select_tiles = conn.prepare_cached(
+            "SELECT zoom_level, tile_column, tile_row, tile_data, rowid FROM tiles WHERE rowid > ?1
+            ORDER BY rowid LIMIT ?2",
+        )?;
+        let rows = select_tiles.query_map(params![last, TileRows::BATCH_SIZE], |row| {
+            Ok((row.get(4)?, read_tile_id(row)?, empty_tile.resolve(conn, row.get(3)?)?))
+        })?;
+        let batch = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(batch)
+    };
+
+    let mut last = i64::MIN;
+    let mut batch = Vec::new().into_iter();
+    let mut done = false;
+
+    std::iter::from_fn(move || loop {
+        if let Some((_, tile_id, tile_data)) = batch.next() {
+            return Some(Ok((tile_id, tile_data)));
+        }
+        if done {
+            return None;
+        }
+
+        match fetch(last) {
+            Ok(rows) => {
+                done = rows.len() < TileRows::BATCH_SIZE as usize;
+                if let Some((rowid, _, _)) = rows.last() {
+                    last = *rowid;
+                }
+                batch = rows.into_iter();
+            }
+            Err(err) => {
+                done = true;
+                return Some(Err(err));
+            }
+        }
+    })
+}
+
+fn tiles_by_hilbert_index(
+    conn: &rusqlite::Connection,
+) -> impl Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_ {
+    let fetch_zooms = || -> rusqlite::Result<Vec<u32>> {
+        let mut select_zooms = conn.prepare_cached("SELECT DISTINCT zoom_level FROM tiles ORDER BY zoom_level")?;
+        let zooms = select_zooms.query_map([], |row| row.get(0))?;
+        let zooms = zooms.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(zooms)
+    };
+
+    let fetch_tile_ids = move |zoom: u32| -> rusqlite::Result<Vec<TmsTileId>> {
+        let mut select_tile_ids =
+            conn.prepare_cached("SELECT zoom_level, tile_column, tile_row FROM tiles WHERE zoom_level = ?1")?;
+        let tile_ids = select_tile_ids.query_map(params![zoom], read_tile_id)?;
+        let mut tile_ids = tile_ids.collect::<rusqlite::Result<Vec<_>>>()?;
+        tile_ids.sort_by_cached_key(|tile_id| hilbert_index(tile_id.z(), tile_id.x(), tile_id.y()));
+        Ok(tile_ids)
+    };
+
+    let (zooms, err) = match fetch_zooms() {
+        Ok(zooms) => (zooms, None),
+        Err(err) => (Vec::new(), Some(Err(err))),
+    };
+
+    err.into_iter().chain(zooms.into_iter().flat_map(move |zoom| {
+        let tiles: Box<dyn Iterator<Item = _> + '_> = match fetch_tile_ids(zoom) {
+            // Tiles deleted in the meantime are skipped
+            Ok(tile_ids) => Box::new(tile_ids.into_iter().filter_map(move |tile_id| {
+                read_tile(conn, tile_id)
+                    .map(|tile_data| tile_data.map(|tile_data| (tile_id, tile_data)))
+                    .transpose()
+            })),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        };
+        tiles
+    }))
+}
+
+/// Returns the distance of the given tile along the Hilbert curve filling its zoom level.
+fn hilbert_index(zoom: u32, x: u32, y: u32) -> u64 {
+    let n = 1u64 << zoom;
+    let (mut x, mut y) = (u64::from(x), u64::from(y));
+    let mut index = 0;
+
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from((x & s) > 0);
+        let ry = u64::from((y & s) > 0);
+        index += s * s * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve continues where the previous one ended
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        s /= 2;
+    }
+
+    index
+}
+
+/// Returns an iterator over all tiles in the database, ordered by zoom level, column and row.
+///
+/// Tiles are fetched in batches, so no statement is kept open between iterations. Tiles stored with invalid
+/// coordinates yield an error, see [`tile_id_from_row`].
+///
+/// **Note:** if the `tiles` table has no unique index, only one of the tiles stored with the same coordinates might be
+/// returned.
+pub fn tiles(conn: &impl AsConnection) -> impl Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_ {
+    let conn = conn.as_connection();
+    TileRows::new(
+        conn,
+        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles \
+        WHERE (zoom_level, tile_column, tile_row) > (?1, ?2, ?3) \
+        ORDER BY zoom_level, tile_column, tile_row LIMIT ?4",
+        Ok(true),
+    )
+    .resolving_empty_tiles()
+}
+
+/// Returns an iterator over all tiles in the database like [`tiles`], but with XYZ scheme tile IDs, as used by most
+/// web maps.
+///
+/// **Note:** the tiles keep the order of [`tiles`], so within a column, rows are in descending XYZ order.
+pub fn tiles_xyz(conn: &impl AsConnection) -> impl Iterator<Item = rusqlite::Result<(TileId, Vec<u8>)>> + '_ {
+    tiles(conn).map(|tile| {
+        let (tile_id, tile_data) = tile?;
+        let (z, x, y) = (tile_id.z(), tile_id.x(), tile_id.y());
+        // TMS and XYZ rows only differ in direction.
+        let tile_id = TileId::new(z, x, (1 << z) - 1 - y).map_err(|_| {
+            let err = InvalidTileCoord { z, x, y };
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Integer, Box::new(err))
+        })?;
+        Ok((tile_id, tile_data))
+    })
+}
+
+/// Calls the given function with every tile of the database at the given path, using the given number of threads.
+///
+/// The `rowid` range of the `tiles` table is split evenly between the threads, and each thread reads its part through
+/// its own read-only connection, since an SQLite connection can't be shared between threads. Tiles are passed to the
+/// function in no particular order. Returns the first error encountered by any of the threads.
+///
+/// **Note:** the `tiles` view of the deduplicating layout has no `rowid`, so such databases aren't supported.
+pub fn par_tiles<P, F>(path: P, threads: usize, f: F) -> rusqlite::Result<()>
+where
+    P: AsRef<Path>,
+    F: Fn(TmsTileId, Vec<u8>) + Sync,
+{
+    let path = path.as_ref();
+
+    let (min, max): (Option<i64>, Option<i64>) =
+        open_read_only(path)?.query_row("SELECT MIN(rowid), MAX(rowid) FROM tiles", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    let (min, max) = match (min, max) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return Ok(()),
+    };
+
+    let threads = threads.max(1) as i64;
+    let chunk_size = (max - min) / threads + 1;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let f = &f;
+                scope.spawn(move || -> rusqlite::Result<()> {
+                    let conn = open_read_only(path)?;
+                    let mut select_tiles = conn.prepare(
+                        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles WHERE rowid BETWEEN ?1 AND ?2",
+                    )?;
+                    let start = min + i * chunk_size;
+                    let mut rows = select_tiles.query(params![start, start + chunk_size - 1])?;
+                    let mut empty_tile = EmptyTile::default();
+
+                    while let Some(row) = rows.next()? {
+                        f(read_tile_id(row)?, empty_tile.resolve(&conn, row.get(3)?)?);
+                    }
+
+                    Ok(())
+                })
+            })
+            .collect();
+
+        handles.into_iter().fold(Ok(()), |result, handle| {
+            let thread_result = handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            result.and(thread_result)
+        })
+    })
+}
+
+/// Returns an asynchronous stream of all tiles in the database at the given path, see [`tiles`].
+///
+/// The tiles are read by a blocking task of the Tokio runtime, which must be running, and passed through a bounded
+/// channel, so reading is paused while the consumer falls behind. Reading stops when the stream is dropped.
+#[cfg(feature = "async")]
+pub fn tile_stream<P: AsRef<Path>>(
+    path: P,
+) -> impl tokio_stream::Stream<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> {
+    const CHANNEL_CAPACITY: usize = 64;
+
+    let path = path.as_ref().to_owned();
+    let (sender, receiver) = tokio::sync::mpsc::channel(CHANNEL_CAPACITY);
+
+    tokio::task::spawn_blocking(move || {
+        let conn = match open_read_only(&path) {
+            Ok(conn) => conn,
+            Err(err) => {
+                let _ = sender.blocking_send(Err(err));
+                return;
+            }
+        };
+
+        for tile in tiles(&conn) {
+            if sender.blocking_send(tile).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio_stream::wrappers::ReceiverStream::new(receiver)
+}
+
+/// Returns an iterator over the tiles within the given zoom levels, ordered by zoom level, column and row.
+///
+/// Like [`tiles`], but only the tiles within the zoom levels are queried, so the tiles at other zoom levels are
+/// skipped using the `tile_index` index instead of being read.
+pub fn tiles_in_zoom_range(
+    conn: &impl AsConnection,
+    zoom_range: RangeInclusive<ZoomLevel>,
+) -> impl Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_ {
+    let conn = conn.as_connection();
+    let mut rows = TileRows::new(
+        conn,
+        "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles \
+        WHERE zoom_level BETWEEN ?5 AND ?6 AND (zoom_level, tile_column, tile_row) > (?1, ?2, ?3) \
+        ORDER BY zoom_level, tile_column, tile_row LIMIT ?4",
+        Ok(true),
+    )
+    .resolving_empty_tiles();
+    rows.zoom_range = Some((zoom_range.start().get(), zoom_range.end().get()));
+    rows
+}
+
+/// Returns an iterator over all [UTFGrid](https://github.com/mapbox/utfgrid-spec) grids in the database, ordered by
+/// zoom level, column and row.
+///
+/// If the optional `grids` table doesn't exist, the iterator is empty.
+pub fn grids(conn: &impl AsConnection) -> impl Iterator<Item = rusqlite::Result<(TmsTileId, Vec<u8>)>> + '_ {
+    let conn = conn.as_connection();
+    TileRows::new(
+        conn,
+        "SELECT zoom_level, tile_column, tile_row, grid FROM grids \
+        WHERE (zoom_level, tile_column, tile_row) > (?1, ?2, ?3) \
+        ORDER BY zoom_level, tile_column, tile_row LIMIT ?4",
+        has_grids(conn),
+    )
+}
+
+/// Iterates over the rows of a table addressed by tile IDs in batches, using keyset pagination.
+struct TileRows<'conn> {
+    conn: &'conn rusqlite::Connection,
+    /// Selects the ID and data of the next rows after the given ID (`?1`, `?2`, `?3`), limited to `?4` rows, and
+    /// within the zoom levels `?5` to `?6` if there's a `zoom_range`.
+    sql: &'static str,
+    last: (i64, i64, i64),
+    zoom_range: Option<(u32, u32)>,
+    batch: std::vec::IntoIter<rusqlite::Result<(TmsTileId, Vec<u8>)>>,
+    done: bool,
+    /// Resolves references to the canonical empty tile, for rows of the `tiles` table.
+    empty_tile: Option<EmptyTile>,
+}
+
+impl<'conn> TileRows<'conn> {
+    const BATCH_SIZE: i64 = 256;
+
+    fn new(conn: &'conn rusqlite::Connection, sql: &'static str, table_exists: rusqlite::Result<bool>) -> Self {
+        let (batch, done) = match table_exists {
+            Ok(exists) => (Vec::new(), !exists),
+            Err(err) => (vec![Err(err)], true),
+        };
+
+        TileRows {
+            conn,
+            sql,
+            last: (-1, 0, 0),
+            zoom_range: None,
+            batch: batch.into_iter(),
+            done,
+            empty_tile: None,
+        }
+    }
+
+    fn resolving_empty_tiles(mut self) -> Self {
+        self.empty_tile = Some(EmptyTile::default());
+        self
+    }
+
+    fn fetch(&mut self) -> rusqlite::Result<Vec<rusqlite::Result<(TmsTileId, Vec<u8>)>>> {
+        let (conn, empty_tile) = (self.conn, &mut self.empty_tile);
+        let mut select_rows = conn.prepare_cached(self.sql)?;
+        let (z, x, y) = self.last;
+        let (min_zoom, max_zoom) = self.zoom_range.unwrap_or_default();
+        let params = [z, x, y, Self::BATCH_SIZE, min_zoom.into(), max_zoom.into()];
+        let param_count = if self.zoom_range.is_some() { 6 } else { 4 };
+        let rows = select_rows.query_map(params_from_iter(&params[..param_count]), |row| {
+            let data = match empty_tile.as_mut() {
+                Some(empty_tile) => empty_tile.resolve(conn, row.get(3)?)?,
+                None => row.get(3)?,
+            };
+            Ok((read_tile_id(row)?, data))
+        })?;
+        let batch = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if batch.len() < Self::BATCH_SIZE as usize {
+            self.done = true;
+        }
+        if let Some((tile_id, _)) = batch.last() {
+            self.last = (tile_id.z().into(), tile_id.x().into(), tile_id.y().into());
+        }
+
+        Ok(batch.into_iter().map(Ok).collect())
+    }
+}
+
+impl<'conn> Iterator for TileRows<'conn> {
+    type Item = rusqlite::Result<(TmsTileId, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.batch.next() {
+                return Some(item);
+            }
+            if self.done {
+                return None;
+            }
+
+            self.batch = match self.fetch() {
+                Ok(batch) => batch.into_iter(),
+                Err(err) => {
+                    self.done = true;
+                    vec![Err(err)].into_iter()
+                }
+            };
+        }
+    }
+}
+
+/// Reads the tile ID from the `zoom_level`, `tile_column` and `tile_row` values in the first three columns of the row.
+///
+/// Invalid coordinates, which can only be found in corrupt databases, result in a
+/// [`FromSqlConversionFailure`](rusqlite::Error::FromSqlConversionFailure) error with an
+/// [`InvalidTileCoord`](crate::common::InvalidTileCoord) cause.
+fn read_tile_id(row: &rusqlite::Row) -> rusqlite::Result<TmsTileId> {
+    let (z, x, y) = (row.get(0)?, row.get(1)?, row.get(2)?);
+    tile_id_from_row(z, x, y)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Integer, Box::new(err)))
+}
+
+/// Reads the given grid from the database.
+///
+/// If the grid is not found, `None` is returned.
+pub fn read_grid(conn: &impl AsConnection, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+    let conn = conn.as_connection();
+    select_first(
+        conn,
+        "SELECT grid FROM grids WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+        params![tile_id.z(), tile_id.x(), tile_id.y()],
+    )
+}
+
+/// Reads the given grid and decompresses it into its JSON, see [`read_grid`].
+#[cfg(feature = "compression")]
+pub fn read_grid_decompressed(conn: &impl AsConnection, tile_id: TmsTileId) -> Result<Option<String>, Box<dyn Error>> {
+    match read_grid(conn, tile_id)? {
+        Some(grid) => Ok(Some(String::from_utf8(gunzip(&grid)?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the grid data for the given key from the database.
+///
+/// If the grid data is not found, `None` is returned.
+pub fn read_grid_data(conn: &impl AsConnection, tile_id: TmsTileId, key: &str) -> rusqlite::Result<Option<String>> {
+    let conn = conn.as_connection();
+    select_first(
+        conn,
+        "SELECT key_json FROM grid_data WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3 AND key_name = ?4",
+        params![tile_id.z(), tile_id.x(), tile_id.y(), key],
+    )
+}
+
+/// Reads the grid with the given XYZ scheme coordinates from the database, see [`read_grid`] and [`read_tile_xyz`].
+pub fn read_grid_xyz(conn: &impl AsConnection, z: u32, x: u32, y: u32) -> rusqlite::Result<Option<Vec<u8>>> {
+    read_grid(conn, xyz_param(z, x, y)?)
+}
+
+/// Reads the grid data for the given key and XYZ scheme coordinates from the database, see [`read_grid_data`] and
+/// [`read_tile_xyz`].
+pub fn read_grid_data_xyz(
+    conn: &impl AsConnection,
+    z: u32,
+    x: u32,
+    y: u32,
+    key: &str,
+) -> rusqlite::Result<Option<String>> {
+    read_grid_data(conn, xyz_param(z, x, y)?, key)
+}
+
+/// Converts XYZ scheme coordinates passed as query parameters into a tile ID.
+fn xyz_param(z: u32, x: u32, y: u32) -> rusqlite::Result<TmsTileId> {
+    tile_id_from_xyz(z, x, y).map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+/// Runs the given query, returning the first column of the first row if there's any.
+fn select_first<T: FromSql, P: Params>(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: P,
+) -> rusqlite::Result<Option<T>> {
+    let mut select = conn.prepare_cached(sql)?;
+    let mut rows = select.query(params)?;
+
+    if let Some(row) = rows.next()? {
+        Ok(Some(row.get(0)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The canonical empty tile which zero-length tiles resolve to, see
+/// [`write_empty_tile_ref`](crate::write::write_empty_tile_ref).
+///
+/// The `empty_tile` table is only looked up once, when the first zero-length tile is resolved.
+#[derive(Debug, Default)]
+struct EmptyTile {
+    /// The quoted schema name followed by a dot, or empty for the unqualified table.
+    schema_prefix: String,
+    tile_data: Option<Option<Vec<u8>>>,
+}
+
+impl EmptyTile {
+    fn with_schema_prefix(schema_prefix: String) -> Self {
+        EmptyTile {
+            schema_prefix,
+            tile_data: None,
+        }
+    }
+
+    /// Returns the canonical empty tile, or `None` if no empty tile is set.
+    fn get(&mut self, conn: &rusqlite::Connection) -> rusqlite::Result<Option<&[u8]>> {
+        if self.tile_data.is_none() {
+            let sql = format!(
+                "SELECT 1 FROM {}sqlite_master WHERE type = 'table' AND name = 'empty_tile'",
+                self.schema_prefix
+            );
+            let tile_data = if conn.prepare_cached(&sql)?.exists([])? {
+                select_first(
+                    conn,
+                    &format!("SELECT tile_data FROM {}empty_tile", self.schema_prefix),
+                    [],
+                )?
+            } else {
+                None
+            };
+            self.tile_data = Some(tile_data);
+        }
+
+        Ok(self.tile_data.as_ref().and_then(|tile_data| tile_data.as_deref()))
+    }
+
+    /// Returns the canonical empty tile if the given tile data is a reference to it, i.e. zero-length and an empty tile
+    /// is set, otherwise the given tile data.
+    fn resolve(&mut self, conn: &rusqlite::Connection, tile_data: Vec<u8>) -> rusqlite::Result<Vec<u8>> {
+        if !tile_data.is_empty() {
+            return Ok(tile_data);
+        }
+        Ok(self.get(conn)?.map(<[u8]>::to_vec).unwrap_or(tile_data))
+    }
+}
+
+/// Reads the canonical empty tile set with [`set_empty_tile`](crate::write::set_empty_tile), or `None` if it isn't
+/// set, in either layout.
+pub fn read_empty_tile(conn: &impl AsConnection) -> rusqlite::Result<Option<Vec<u8>>> {
+    let conn = conn.as_connection();
+    if table_exists(conn, "map")? {
+        select_first(
+            conn,
+            "SELECT tile_data FROM images WHERE tile_id = ?1",
+            params![EMPTY_TILE_ID],
+        )
+    } else {
+        Ok(EmptyTile::default().get(conn)?.map(<[u8]>::to_vec))
+    }
+}
+
+/// Quotes the given SQL identifier, e.g. a table or schema name.
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Reads an MBTiles database through tables qualified with a schema name.
+///
+/// This allows reading databases attached with `ATTACH DATABASE 'other.mbtiles' AS other`, or explicitly targeting
+/// the `main` or `temp` schemas.
+pub struct MbtilesReader<'conn> {
+    conn: &'conn rusqlite::Connection,
+    schema: Option<String>,
+    table_names: TableNames,
+    empty_tile: RefCell<EmptyTile>,
+}
+
+/// The names of the tables read by an [`MbtilesReader`], for MBTiles-like databases which don't use the names of the
+/// specification.
+///
+/// The column names of the tables are still expected to match the specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableNames {
+    pub metadata: String,
+    pub tiles: String,
+    pub grids: String,
+    pub grid_data: String,
+}
+
+impl Default for TableNames {
+    fn default() -> Self {
+        TableNames {
+            metadata: "metadata".to_owned(),
+            tiles: "tiles".to_owned(),
+            grids: "grids".to_owned(),
+            grid_data: "grid_data".to_owned(),
+        }
+    }
+}
+
+impl<'conn> MbtilesReader<'conn> {
+    /// Creates a reader for the unqualified tables of the given connection, like the free functions of this module.
+    pub fn new<C: AsConnection>(conn: &'conn C) -> Self {
+        MbtilesReader {
+            conn: conn.as_connection(),
+            schema: None,
+            table_names: TableNames::default(),
+            empty_tile: RefCell::new(EmptyTile::default()),
+        }
+    }
+
+    /// Creates a reader for the tables of the given schema of the connection, e.g. `main` or an attached database.
+    pub fn with_schema<C: AsConnection>(conn: &'conn C, schema: &str) -> Self {
+        MbtilesReader {
+            conn: conn.as_connection(),
+            schema: Some(schema.to_owned()),
+            table_names: TableNames::default(),
+            empty_tile: RefCell::new(EmptyTile::with_schema_prefix(format!("{}.", quote_identifier(schema)))),
+        }
+    }
+
+    /// Makes the reader read the tables with the given names instead of the names of the specification.
+    pub fn with_table_names(mut self, table_names: TableNames) -> Self {
+        self.table_names = table_names;
+        self
+    }
+
+    /// Returns the quoted name of the given table, qualified with the schema name if there's one.
+    fn table(&self, name: &str) -> String {
+        match &self.schema {
+            Some(schema) => format!("{}.{}", quote_identifier(schema), quote_identifier(name)),
+            None => quote_identifier(name),
+        }
+    }
+
+    /// Reads metadata, see [`read_metadata`].
+    pub fn metadata(&self) -> Result<Metadata, Box<dyn Error>> {
+        Ok(read_metadata_impl(
+            self.conn,
+            &self.table(&self.table_names.metadata),
+            &self.table(&self.table_names.tiles),
+            false,
+        )?
+        .0)
+    }
+
+    /// Reads metadata, failing on malformed values, see [`read_metadata_strict`].
+    pub fn metadata_strict(&self) -> Result<Metadata, Box<dyn Error>> {
+        Ok(read_metadata_impl(
+            self.conn,
+            &self.table(&self.table_names.metadata),
+            &self.table(&self.table_names.tiles),
+            true,
+        )?
+        .0)
+    }
+
+    /// Reads the given tile, see [`read_tile`].
+    ///
+    /// References to the canonical empty tile are resolved with the `empty_tile` table of the schema, which is only
+    /// looked up once.
+    pub fn tile(&self, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+        let sql = format!(
+            "SELECT tile_data FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            self.table(&self.table_names.tiles)
+        );
+        let tile_data: Option<Vec<u8>> = select_first(self.conn, &sql, params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+
+        let mut empty_tile = self.empty_tile.borrow_mut();
+        tile_data
+            .map(|tile_data| empty_tile.resolve(self.conn, tile_data))
+            .transpose()
+    }
+
+    /// Reads the tile with the given coordinates, interpreting the row according to the `scheme` metadata row of the
+    /// tileset, see [`Scheme`].
+    ///
+    /// The row is flipped unless the tileset declares the [`Xyz`](Scheme::Xyz) scheme, as the coordinates of the
+    /// `tiles` table are TMS coordinates by default. Unknown schemes are treated like the default.
+    pub fn tile_auto(&self, z: u32, x: u32, y: u32) -> rusqlite::Result<Option<Vec<u8>>> {
+        let sql = format!(
+            "SELECT value FROM {} WHERE name = 'scheme'",
+            self.table(&self.table_names.metadata)
+        );
+        let scheme: Option<String> = select_first(self.conn, &sql, [])?;
+
+        let tile_id = match scheme.as_deref().map(Scheme::try_from) {
+            Some(Ok(Scheme::Xyz)) => tile_id_from_row(z, x, y),
+            _ => tile_id_from_xyz(z, x, y),
+        }
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+
+        self.tile(tile_id)
+    }
+
+    /// Reads the given grid, see [`read_grid`].
+    pub fn grid(&self, tile_id: TmsTileId) -> rusqlite::Result<Option<Vec<u8>>> {
+        let sql = format!(
+            "SELECT grid FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            self.table(&self.table_names.grids)
+        );
+        select_first(self.conn, &sql, params![tile_id.z(), tile_id.x(), tile_id.y()])
+    }
+
+    /// Reads the grid data for the given key, see [`read_grid_data`].
+    pub fn grid_data(&self, tile_id: TmsTileId, key: &str) -> rusqlite::Result<Option<String>> {
+        let sql = format!(
+            "SELECT key_json FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3 AND key_name = ?4",
+            self.table(&self.table_names.grid_data)
+        );
+        select_first(self.conn, &sql, params![tile_id.z(), tile_id.x(), tile_id.y(), key])
+    }
+}
+
+/// An overview of the tile data stored in a database.
+#[derive(Debug)]
+pub struct TilesetSummary {
+    /// The number of stored tiles.
+    pub tile_count: u64,
+    /// The lowest and highest zoom levels of the stored tiles, `None` if there are no tiles.
+    pub zoom_range: Option<RangeInclusive<u32>>,
+    /// The total size of the tile data in bytes.
+    pub total_tile_bytes: u64,
+    /// The compression of the tile data, guessed from the first tile.
+    pub compression: Compression,
+}
+
+/// Differences between the tiles of two databases, see [`diff`].
+#[derive(Debug, Default)]
+pub struct TileDiff {
+    /// Tiles only present in the second database.
+    pub added: Vec<TmsTileId>,
+    /// Tiles only present in the first database.
+    pub removed: Vec<TmsTileId>,
+    /// Tiles present in both databases with different data.
+    pub changed: Vec<TmsTileId>,
+    /// Number of tiles present in both databases with the same data.
+    pub unchanged: u64,
+}
+
+/// Compares the tiles of the two databases, classifying each tile as added, removed, changed or unchanged in `b`
+/// relative to `a`.
+///
+/// Both databases are read in tile order at the same time, so only a batch of tiles of each is held in memory.
+pub fn diff(a: &impl AsConnection, b: &impl AsConnection) -> rusqlite::Result<TileDiff> {
+    let mut result = TileDiff::default();
+
+    let key = |tile_id: &TmsTileId| (tile_id.z(), tile_id.x(), tile_id.y());
+
+    let (mut a_tiles, mut b_tiles) = (tiles(a), tiles(b));
+    let (mut a_tile, mut b_tile) = (a_tiles.next().transpose()?, b_tiles.next().transpose()?);
+
+    loop {
+        match (&a_tile, &b_tile) {
+            (None, None) => break,
+            (Some((a_id, _)), None) => {
+                result.removed.push(*a_id);
+                a_tile = a_tiles.next().transpose()?;
+            }
+            (None, Some((b_id, _))) => {
+                result.added.push(*b_id);
+                b_tile = b_tiles.next().transpose()?;
+            }
+            (Some((a_id, a_data)), Some((b_id, b_data))) => match key(a_id).cmp(&key(b_id)) {
+                std::cmp::Ordering::Less => {
+                    result.removed.push(*a_id);
+                    a_tile = a_tiles.next().transpose()?;
+                }
+                std::cmp::Ordering::Greater => {
+                    result.added.push(*b_id);
+                    b_tile = b_tiles.next().transpose()?;
+                }
+                std::cmp::Ordering::Equal => {
+                    if a_data == b_data {
+                        result.unchanged += 1;
+                    } else {
+                        result.changed.push(*a_id);
+                    }
+                    a_tile = a_tiles.next().transpose()?;
+                    b_tile = b_tiles.next().transpose()?;
+                }
+            },
+        }
+    }
+
+    Ok(result)
+}
+
+/// Returns the tiles at the given zoom level which are expected within the `bounds` but missing from the database, in
+/// tile order, e.g. to find gaps left by a failed ingest.
+///
+/// If the `bounds` row is absent, the tiles within the columns and rows of the stored tiles at the zoom level are
+/// expected instead, so a zoom level without tiles has no missing ones. Malformed bounds result in a
+/// [`FromSqlConversionFailure`](rusqlite::Error::FromSqlConversionFailure) error with a [`MalformedMetadata`] cause.
+///
+/// **Note:** the expected tiles are enumerated and the missing ones collected, so time and memory grow with the area
+/// at the zoom level, e.g. bounds covering the whole world expect 268 million tiles at zoom level 14.
+pub fn missing_tiles(conn: &impl AsConnection, zoom: ZoomLevel) -> rusqlite::Result<Vec<TmsTileId>> {
+    let conn = conn.as_connection();
+    let zoom = zoom.get();
+
+    let ranges = match get_metadata_value(conn, "bounds")? {
+        Some(value) => match parse_bounds(&value) {
+            Ok(ParsedBounds::Regular(bounds)) => vec![rect_to_tile_range(&bounds, zoom)],
+            Ok(ParsedBounds::Antimeridian(west, east)) => {
+                vec![rect_to_tile_range(&west, zoom), rect_to_tile_range(&east, zoom)]
+            }
+            Err(reason) => {
+                let err = MalformedMetadata::new("bounds", value, reason);
+                return Err(rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(err),
+                ));
+            }
+        },
+        None => {
+            let mut select_range = conn.prepare_cached(
+                "SELECT MIN(tile_column), MAX(tile_column), MIN(tile_row), MAX(tile_row) FROM tiles
+                WHERE zoom_level = ?1",
+            )?;
+            let range: (Option<u32>, Option<u32>, Option<u32>, Option<u32>) = select_range
+                .query_row(params![zoom], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?;
+            match range {
+                (Some(min_column), Some(max_column), Some(min_row), Some(max_row)) => {
+                    vec![(min_column..=max_column, min_row..=max_row)]
+                }
+                _ => return Ok(Vec::new()),
+            }
+        }
+    };
+
+    let mut select_tiles = conn.prepare_cached(
+        "SELECT tile_column, tile_row FROM tiles WHERE zoom_level = ?1
+        AND tile_column BETWEEN ?2 AND ?3 AND tile_row BETWEEN ?4 AND ?5",
+    )?;
+
+    let mut missing = Vec::new();
+
+    for (columns, rows) in ranges {
+        let existing = select_tiles
+            .query_map(
+                params![zoom, columns.start(), columns.end(), rows.start(), rows.end()],
+                |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?)),
+            )?
+            .collect::<rusqlite::Result<HashSet<_>>>()?;
+
+        for column in columns {
+            for row in rows.clone() {
+                if !existing.contains(&(column, row)) {
+                    let tile_id = tile_id_from_row(zoom, column, row)
+                        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+                    missing.push(tile_id);
+                }
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Computes the bounds of the tiles at each zoom level, which are tighter than the overall `bounds` for sparse
+/// tilesets.
+///
+/// The bounds of a zoom level cover the extent of its tiles, from the westernmost column and southernmost row to the
+/// easternmost column and northernmost row.
+pub fn compute_zoom_bounds(conn: &impl AsConnection) -> rusqlite::Result<BTreeMap<u32, GeoRect>> {
+    let conn = conn.as_connection();
+
+    let mut select_ranges = conn.prepare_cached(
+        "SELECT zoom_level, MIN(tile_column), MIN(tile_row), MAX(tile_column), MAX(tile_row) FROM tiles
+        GROUP BY zoom_level",
+    )?;
+    let mut rows = select_ranges.query([])?;
+
+    let mut zoom_bounds = BTreeMap::new();
+
+    while let Some(row) = rows.next()? {
+        let south_west = read_tile_id(row)?;
+        let north_east = tile_id_from_row(row.get(0)?, row.get(3)?, row.get(4)?).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Integer, Box::new(err))
+        })?;
+
+        let (west, south, _, _) = tile_extent(south_west.z(), south_west.x(), south_west.y());
+        let (_, _, east, north) = tile_extent(north_east.z(), north_east.x(), north_east.y());
+
+        let tl = GeoCoord::from_degrees(west, north).expect("tile extents are valid");
+        let br = GeoCoord::from_degrees(east, south).expect("tile extents are valid");
+        zoom_bounds.insert(south_west.z(), GeoRect::new(tl, br).expect("tile extents are valid"));
+    }
+
+    Ok(zoom_bounds)
+}
+
+/// Counts the tiles at the given zoom level.
+pub fn count_tiles_at_zoom(conn: &impl AsConnection, zoom: ZoomLevel) -> rusqlite::Result<u64> {
+    let conn = conn.as_connection();
+    let mut count_tiles = conn.prepare_cached("SELECT COUNT(*) FROM tiles WHERE zoom_level = ?1")?;
+    let count: i64 = count_tiles.query_row(params![zoom.get()], |row| row.get(0))?;
+    Ok(count as u64)
+}
+
+/// Counts the tiles at each zoom level which has any, using a single query.
+///
+/// Tiles stored with a zoom level above [`MAX_ZOOM_LEVEL`](crate::common::MAX_ZOOM_LEVEL) result in a
+/// [`FromSqlConversionFailure`](rusqlite::Error::FromSqlConversionFailure) error.
+pub fn zoom_histogram(conn: &impl AsConnection) -> rusqlite::Result<BTreeMap<ZoomLevel, u64>> {
+    let conn = conn.as_connection();
+    let mut count_tiles = conn.prepare_cached("SELECT zoom_level, COUNT(*) FROM tiles GROUP BY zoom_level")?;
+    let counts = count_tiles.query_map([], |row| {
+        let zoom = ZoomLevel::try_from(row.get::<_, u32>(0)?).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Integer, Box::new(err))
+        })?;
+        Ok((zoom, row.get::<_, i64>(1)? as u64))
+    })?;
+    counts.collect()
+}
+
+/// Checks whether the database has any tile at the given zoom level, without counting them.
+pub fn has_zoom(conn: &impl AsConnection, zoom: ZoomLevel) -> rusqlite::Result<bool> {
+    let conn = conn.as_connection();
+    let mut select_tile = conn.prepare_cached("SELECT 1 FROM tiles WHERE zoom_level = ?1 LIMIT 1")?;
+    select_tile.exists(params![zoom.get()])
+}
+
+/// Returns the [Bing Maps quadkeys](https://docs.microsoft.com/en-us/bingmaps/articles/bing-maps-tile-system) of the
+/// tiles at the given zoom level, in tile order.
+///
+/// Quadkeys address tiles with rows counted from the north, so the TMS rows are flipped. The quadkey of the single
+/// tile at zoom level 0 is the empty string.
+pub fn coverage_quadkeys(conn: &impl AsConnection, zoom: ZoomLevel) -> rusqlite::Result<Vec<String>> {
+    let conn = conn.as_connection();
+    let zoom = zoom.get();
+    let mut select_tiles = conn.prepare_cached(
+        "SELECT DISTINCT tile_column, tile_row FROM tiles WHERE zoom_level = ?1 ORDER BY tile_column, tile_row",
+    )?;
+    let quadkeys = select_tiles.query_map(params![zoom], |row| {
+        let tile_id = tile_id_from_row(zoom, row.get(0)?, row.get(1)?).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Integer, Box::new(err))
+        })?;
+        Ok(quadkey(tile_id))
+    })?;
+    quadkeys.collect()
+}
+
+/// Converts the given tile ID into a quadkey, interleaving the bits of the column and the XYZ row.
+fn quadkey(tile_id: TmsTileId) -> String {
+    let z = tile_id.z();
+    let xyz_row = (1 << z) - 1 - tile_id.y();
+    (1..=z)
+        .rev()
+        .map(|level| {
+            let mask = 1 << (level - 1);
+            let digit = (tile_id.x() & mask != 0) as u8 + 2 * (xyz_row & mask != 0) as u8;
+            char::from(b'0' + digit)
+        })
+        .collect()
+}
+
+/// Computes the statistics of a database using the deduplicating `map`/`images` layout, see [`DedupStats`].
+pub fn dedup_stats(conn: &impl AsConnection) -> rusqlite::Result<DedupStats> {
+    let conn = conn.as_connection();
+    let (map_rows, tile_bytes) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(images.tile_data)), 0)
+        FROM map LEFT JOIN images ON images.tile_id = map.tile_id",
+        [],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+    )?;
+    let (image_rows, image_bytes) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(tile_data)), 0) FROM images",
+        [],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+    )?;
+
+    Ok(DedupStats {
+        map_rows: map_rows as u64,
+        image_rows: image_rows as u64,
+        tile_bytes: tile_bytes as u64,
+        image_bytes: image_bytes as u64,
+    })
+}
+
+/// Summarizes the tile data stored in the database.
+///
+/// Sizes are those of the stored tile data, so outside of the deduplicating layout references to the empty tile (see
+/// [`write_empty_tile_ref`](crate::write::write_empty_tile_ref)) count as zero-length tiles.
+///
+/// The compression is detected from the first tile which isn't such a reference.
+pub fn summary(conn: &impl AsConnection) -> rusqlite::Result<TilesetSummary> {
+    let conn = conn.as_connection();
+    let mut select_summary = conn.prepare_cached(
+        "SELECT COUNT(*), MIN(zoom_level), MAX(zoom_level), COALESCE(SUM(LENGTH(tile_data)), 0) FROM tiles",
+    )?;
+    let (tile_count, minzoom, maxzoom, total_tile_bytes) = select_summary.query_row([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<u32>>(1)?,
+            row.get::<_, Option<u32>>(2)?,
+            row.get::<_, i64>(3)?,
+        ))
+    })?;
+
+    let mut select_first_tile =
+        conn.prepare_cached("SELECT tile_data FROM tiles WHERE LENGTH(tile_data) > 0 LIMIT 1")?;
+    let mut rows = select_first_tile.query([])?;
+
+    let compression = if let Some(row) = rows.next()? {
+        let tile_data: Vec<u8> = row.get(0)?;
+        detect_compression(&tile_data)
+    } else {
+        Compression::None
+    };
+
+    let zoom_range = match (minzoom, maxzoom) {
+        (Some(minzoom), Some(maxzoom)) => Some(minzoom..=maxzoom),
+        _ => None,
+    };
+
+    Ok(TilesetSummary {
+        tile_count: tile_count as u64,
+        zoom_range,
+        total_tile_bytes: total_tile_bytes as u64,
+        compression,
+    })
+}
+
+/// Size statistics of the tile data stored in a database, in bytes.
+#[derive(Debug)]
+pub struct TileSizeStats {
+    /// The number of stored tiles.
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub avg: f64,
+    pub total: u64,
+    /// The ID of the largest tile, `None` if there are no tiles.
+    pub largest: Option<TmsTileId>,
+}
+
+/// Computes size statistics of the tile data stored in the database.
+///
+/// Like in [`summary`], references to the empty tile count as zero-length tiles outside of the deduplicating layout.
+pub fn tile_size_stats(conn: &impl AsConnection) -> rusqlite::Result<TileSizeStats> {
+    let conn = conn.as_connection();
+    let mut select_stats = conn.prepare_cached(
+        "SELECT COUNT(*), COALESCE(MIN(LENGTH(tile_data)), 0), COALESCE(MAX(LENGTH(tile_data)), 0), \
+        COALESCE(AVG(LENGTH(tile_data)), 0.0), COALESCE(SUM(LENGTH(tile_data)), 0) FROM tiles",
+    )?;
+    let (count, min, max, avg, total) = select_stats.query_row([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, f64>(3)?,
+            row.get::<_, i64>(4)?,
+        ))
+    })?;
+
+    let largest = largest_tiles(conn, 1)?.pop().map(|(tile_id, _)| tile_id);
+
+    Ok(TileSizeStats {
+        count: count as u64,
+        min: min as u64,
+        max: max as u64,
+        avg,
+        total: total as u64,
+        largest,
+    })
+}
+
+/// Returns the IDs and sizes (in bytes) of the `n` largest tiles, in descending order of size.
+///
+/// See [`tile_size_stats`] for how references to the empty tile are counted.
+pub fn largest_tiles(conn: &impl AsConnection, n: u32) -> rusqlite::Result<Vec<(TmsTileId, u64)>> {
+    let conn = conn.as_connection();
+    let mut select_tiles = conn.prepare_cached(
+        "SELECT zoom_level, tile_column, tile_row, LENGTH(tile_data) AS size FROM tiles ORDER BY size DESC LIMIT ?1",
+    )?;
+    let tiles = select_tiles.query_map(params![n], |row| Ok((read_tile_id(row)?, row.get::<_, i64>(3)? as u64)))?;
+    tiles.collect()
+}
+
+/// Checks whether a table with the given name exists in the database.
+///
+/// This allows setup code to skip creating tables which already exist.
+pub fn table_exists(conn: &impl AsConnection, name: &str) -> rusqlite::Result<bool> {
+    let conn = conn.as_connection();
+    let mut select_table = conn.prepare_cached("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
+    select_table.exists(params![name])
+}
+
+/// Checks whether the `metadata` table exists in the database.
+pub fn has_metadata(conn: &impl AsConnection) -> rusqlite::Result<bool> {
+    table_exists(conn, "metadata")
+}
+
+/// Checks whether the `tiles` table (or view, for deduplicated databases) exists in the database.
+pub fn has_tiles(conn: &impl AsConnection) -> rusqlite::Result<bool> {
+    let conn = conn.as_connection();
+    let mut select_tiles =
+        conn.prepare_cached("SELECT 1 FROM sqlite_master WHERE type IN ('table', 'view') AND name = 'tiles'")?;
+    select_tiles.exists([])
+}
+
+/// Checks whether the optional `grids` table exists in the database.
+pub fn has_grids(conn: &impl AsConnection) -> rusqlite::Result<bool> {
+    table_exists(conn, "grids")
+}
+
+/// Checks the SQLite database file for low-level corruption, e.g. of truncated downloads, with `PRAGMA quick_check`
+/// and `PRAGMA integrity_check`.
+///
+/// Returns all problems reported by SQLite, including errors running the checks themselves. Unlike
+/// [`validate_schema`], this doesn't check whether the database is a valid MBTiles database.
+pub fn check_integrity(conn: &impl AsConnection) -> Result<(), Vec<String>> {
+    let conn = conn.as_connection();
+
+    let mut problems: Vec<String> = Vec::new();
+
+    for check in ["quick_check", "integrity_check"].iter() {
+        let result = conn
+            .prepare(&format!("PRAGMA {}", check))
+            .and_then(|mut select_problems| {
+                let lines = select_problems.query_map([], |row| row.get::<_, String>(0))?;
+                lines.collect::<rusqlite::Result<Vec<_>>>()
+            });
+
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    if line != "ok" && !problems.contains(&line) {
+                        problems.push(line);
+                    }
+                }
+            }
+            Err(err) => problems.push(format!("{} failed: {}", check, err)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Error for a database which doesn't have the schema of an MBTiles database, see [`validate_schema`].
+#[derive(Debug)]
+pub enum SchemaError {
+    /// A required table is missing.
+    MissingTable(&'static str),
+    /// A required column of a table is missing.
+    MissingColumn { table: &'static str, column: &'static str },
+    /// The `tile_data` column contains integers, which are references into another table of a nonstandard layout
+    /// rather than tile data.
+    ReferencedTileData,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::MissingTable(table) => write!(f, "missing table {}", table),
+            SchemaError::MissingColumn { table, column } => write!(f, "missing column {} of table {}", column, table),
+            SchemaError::ReferencedTileData => write!(
+                f,
+                "tile_data contains references into another table instead of tile data, which needs a reader aware of \
+                the layout"
+            ),
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+/// Checks that the database has the tables and columns required by the MBTiles specification, failing with a
+/// [`SchemaError`] if it doesn't.
+///
+/// Besides the schema, the type of the first tile's data is checked to detect nonstandard layouts referencing the tile
+/// data in another table, which would otherwise be read as garbage tile data.
+pub fn validate_schema(conn: &impl AsConnection) -> Result<(), Box<dyn Error>> {
+    let conn = conn.as_connection();
+
+    if !has_metadata(conn)? {
+        return Err(Box::new(SchemaError::MissingTable("metadata")));
+    }
+    if !has_tiles(conn)? {
+        return Err(Box::new(SchemaError::MissingTable("tiles")));
+    }
+
+    let required_columns: [(&'static str, &[&'static str]); 2] = [
+        ("metadata", &["name", "value"]),
+        ("tiles", &["zoom_level", "tile_column", "tile_row", "tile_data"]),
+    ];
+
+    for &(table, columns) in required_columns.iter() {
+        let mut select_columns = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let existing = select_columns
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for &column in columns {
+            if !existing.iter().any(|existing| existing.eq_ignore_ascii_case(column)) {
+                return Err(Box::new(SchemaError::MissingColumn { table, column }));
+            }
+        }
+    }
+
+    let tile_data_type: Option<String> = select_first(conn, "SELECT typeof(tile_data) FROM tiles LIMIT 1", [])?;
+    if tile_data_type.as_deref() == Some("integer") {
+        return Err(Box::new(SchemaError::ReferencedTileData));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod mbtiles_read_test {
+    use super::*;
+
+    #[test]
+    fn read_mvt_metadata() {
+        let json = r#"{
+            "vector_layers": [
+                {
+                    "id": "tl_2016_us_county",
+                    "description": "Census counties",
+                    "minzoom": 0,
+                    "maxzoom": 5,
+                    "fields": {
+                        "ALAND": "Number",
+                        "AWATER": "Number",
+                        "GEOID": "String",
+                        "MTFCC": "String",
+                        "NAME": "String"
+                    }
+                }
+            ]
+        }"#;
+
+        let mvt_json = serde_json::from_str::<MvtMetadata>(json);
+
+        assert!(mvt_json.is_ok());
+    }
+
+    #[test]
+    fn xyz_tile_ids() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            INSERT INTO tiles VALUES (2, 1, 0, x'00'), (2, 1, 3, x'01');",
+        )
+        .unwrap();
+
+        let tiles: Vec<_> = tiles_xyz(&conn)
+            .map(|tile| {
+                let (tile_id, tile_data) = tile.unwrap();
+                (tile_id.z(), tile_id.x(), tile_id.y(), tile_data)
+            })
+            .collect();
+
+        assert_eq!(tiles, vec![(2, 1, 3, vec![0]), (2, 1, 0, vec![1])]);
+    }
+
+    #[test]
+    fn tar_header_checksum() {
+        let header = tar_header("0/0/0", 3);
+
+        assert_eq!(&header[124..136], b"00000000003\0");
+
+        let checksum = std::str::from_utf8(&header[148..154]).unwrap();
+        let expected: u32 = header[..148]
+            .iter()
+            .chain(&[b' '; 8])
+            .chain(&header[156..])
+            .map(|&byte| u32::from(byte))
+            .sum();
+        assert_eq!(u32::from_str_radix(checksum, 8).unwrap(), expected);
+    }
+
+    #[test]
+    fn length_prefixed_framing() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            INSERT INTO tiles VALUES (1, 1, 0, x'0102'), (0, 0, 0, x'');",
+        )
+        .unwrap();
+
+        let mut archive = Vec::new();
+        assert_eq!(write_tiles_to(&conn, &mut archive, Framing::LengthPrefixed).unwrap(), 2);
+
+        let mut tiles = Vec::new();
+        let mut rest = &archive[..];
+        while !rest.is_empty() {
+            let mut header = [0; 4];
+            for value in &mut header {
+                let (bytes, tail) = rest.split_at(4);
+                *value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                rest = tail;
+            }
+            let (tile_data, tail) = rest.split_at(header[3] as usize);
+            tiles.push((header[0], header[1], header[2], tile_data.to_vec()));
+            rest = tail;
+        }
+
+        assert_eq!(tiles, vec![(0, 0, 0, vec![]), (1, 1, 0, vec![1, 2])]);
+    }
+
+    #[test]
+    fn par_tiles_visits_each_tile_once() {
+        let path = std::env::temp_dir().join(format!("rosm_mbtiles_par_tiles_{}.mbtiles", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB)",
+            [],
+        )
+        .unwrap();
+        for x in 0..16 {
+            conn.execute("INSERT INTO tiles VALUES (4, ?1, 0, x'00')", [x]).unwrap();
+        }
+        // Leaves gaps in the rowids
+        conn.execute("DELETE FROM tiles WHERE tile_column % 5 = 1", []).unwrap();
+        drop(conn);
+
+        let visited = std::sync::Mutex::new(Vec::new());
+        par_tiles(&path, 3, |tile_id, _| visited.lock().unwrap().push(tile_id.x())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut visited = visited.into_inner().unwrap();
+        visited.sort_unstable();
+        let expected: Vec<u32> = (0..16).filter(|x| x % 5 != 1).collect();
+        assert_eq!(visited, expected);
+    }
+
+    #[test]
+    fn summarize_tiles() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB)",
+            [],
+        )
+        .unwrap();
+
+        let empty = summary(&conn).unwrap();
+        assert_eq!(empty.tile_count, 0);
+        assert_eq!(empty.zoom_range, None);
+        assert_eq!(empty.total_tile_bytes, 0);
+        assert!(matches!(empty.compression, Compression::None));
+
+        conn.execute_batch(
+            "INSERT INTO tiles VALUES
+                (0, 0, 0, x''), (2, 0, 0, x'1f8b0800'), (4, 1, 1, x'1f8b08'), (3, 0, 0, x'1f8b0800000000');",
+        )
+        .unwrap();
+
+        let tileset = summary(&conn).unwrap();
+        assert_eq!(tileset.tile_count, 4);
+        assert_eq!(tileset.zoom_range, Some(0..=4));
+        assert_eq!(tileset.total_tile_bytes, 14);
+        assert!(matches!(tileset.compression, Compression::Gzip));
+    }
+
+    #[test]
+    fn diff_tiles() {
+        let open = |values| {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            conn.execute_batch(&format!(
+                "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+                INSERT INTO tiles VALUES {};",
+                values
+            ))
+            .unwrap();
+            conn
+        };
+        let a = open("(0, 0, 0, x'00'), (1, 0, 0, x'01'), (1, 1, 0, x'02'), (2, 0, 0, x'03')");
+        let b = open("(1, 0, 0, x'01'), (1, 1, 0, x'ff'), (2, 0, 0, x'03'), (2, 1, 1, x'04'), (3, 0, 0, x'05')");
+
+        let coords = |tile_ids: &[TmsTileId]| -> Vec<_> {
+            tile_ids
+                .iter()
+                .map(|tile_id| (tile_id.z(), tile_id.x(), tile_id.y()))
+                .collect()
+        };
+
+        let result = diff(&a, &b).unwrap();
+        assert_eq!(coords(&result.added), vec![(2, 1, 1), (3, 0, 0)]);
+        assert_eq!(coords(&result.removed), vec![(0, 0, 0)]);
+        assert_eq!(coords(&result.changed), vec![(1, 1, 0)]);
+        assert_eq!(result.unchanged, 2);
+
+        let result = diff(&b, &b).unwrap();
+        assert!(result.added.is_empty() && result.removed.is_empty() && result.changed.is_empty());
+        assert_eq!(result.unchanged, 5);
+    }
+
+    #[test]
+    fn read_tiles_in_small_polygon() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            INSERT INTO tiles VALUES (1, 0, 0, x'00'), (1, 0, 1, x'01'), (1, 1, 0, x'02'), (1, 1, 1, x'03');",
+        )
+        .unwrap();
+
+        // Within the north-eastern tile, without containing any of its corners
+        let polygon: Vec<_> = [(10.0, 10.0), (20.0, 10.0), (15.0, 20.0)]
+            .iter()
+            .map(|&(lon, lat)| GeoCoord::from_degrees(lon, lat).unwrap())
+            .collect();
+
+        let tiles = read_tiles_in_polygon(&conn, &polygon, 1).unwrap();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].1, vec![3]);
+    }
+
+    #[test]
+    fn hilbert_curve_order() {
+        let order: Vec<_> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .iter()
+            .map(|&(x, y)| hilbert_index(1, x, y))
+            .collect();
+        assert_eq!(order, vec![0, 1, 2, 3]);
+        assert_eq!(hilbert_index(0, 0, 0), 0);
+    }
+
+    #[test]
+    fn tile_quadkeys() {
+        assert_eq!(quadkey(tile_id_from_row(0, 0, 0).unwrap()), "");
+        // The XYZ tile 3/3/5
+        assert_eq!(quadkey(tile_id_from_row(3, 3, 2).unwrap()), "213");
+    }
+
+    #[test]
+    fn read_vector_layers_lazily() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE metadata (name TEXT, value TEXT)", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO metadata VALUES ('json', ?1)",
+            [r#"{"vector_layers": [{"id": "roads", "fields": {}}], "tilestats": {"unused": [1, 2, 3]}}"#],
+        )
+        .unwrap();
+
+        let mvt_metadata = read_mvt_metadata_lazy(&conn).unwrap();
+
+        assert_eq!(mvt_metadata.vector_layers.len(), 1);
+        assert_eq!(mvt_metadata.vector_layers[0].id, "roads");
+        assert!(mvt_metadata.tilestats.is_none());
+    }
+
+    #[test]
+    fn detect_referenced_tile_data() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metadata (name TEXT, value TEXT);
+            CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);",
+        )
+        .unwrap();
+        assert!(validate_schema(&conn).is_ok());
+
+        conn.execute("INSERT INTO tiles VALUES (0, 0, 0, 42)", []).unwrap();
+        let err = validate_schema(&conn).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SchemaError::ReferencedTileData)));
+    }
+
+    #[test]
+    fn read_raster_json() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        let tr = conn.transaction().unwrap();
+        crate::write::create_metadata_table(&tr).unwrap();
+        tr.execute_batch(
+            r#"INSERT INTO metadata (name, value) VALUES
+                ('name', 'test'),
+                ('format', 'webp'),
+                ('json', '{"template": "{{NAME}}"}')"#,
+        )
+        .unwrap();
+        tr.commit().unwrap();
+
+        let metadata = read_metadata(&conn).unwrap();
+
+        assert!(matches!(metadata.format, FileFormat::Webp));
+        assert_eq!(metadata.raw_json.as_deref(), Some(r#"{"template": "{{NAME}}"}"#));
+        assert_eq!(
+            metadata
+                .raster_json
+                .and_then(|raster_json| raster_json.template)
+                .as_deref(),
+            Some("{{NAME}}")
+        );
+    }
+
+    #[test]
+    fn parse_bounds_leniently() {
+        assert!(parse_bounds(" -180, -85.0511 ,180,85.0511 ").is_ok());
+        assert!(parse_bounds("-180,-85.0511,180").is_err());
+        assert!(parse_bounds("-180,south,180,85.0511").is_err());
+        assert!(matches!(
+            parse_bounds("177,-20,-178,-12"),
+            Ok(ParsedBounds::Antimeridian(_, _))
+        ));
+    }
+
+    #[test]
+    fn parse_center_leniently() {
+        assert_eq!(parse_center("19.04, 47.49, 4").map(|(_, zoom)| zoom), Ok(4));
+        assert_eq!(parse_center("19.04,47.49,4.0").map(|(_, zoom)| zoom), Ok(4));
+        assert!(parse_center("19.04,47.49,4.5").is_err());
     }
 }
@@ -1,111 +1,364 @@
 //! Functions for writing MBTiles databases.
 
+use rosm_geo::coord::GeoCoord;
 use rosm_geo::mercator::TmsTileId;
+use rosm_geo::rect::GeoRect;
 
-use rusqlite::{params, Transaction};
+use rusqlite::{params, Connection, Transaction};
 
-use crate::common::{FileFormat, Metadata};
+#[cfg(feature = "compression")]
+use serde::Serialize;
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::common::{
+    detect_compression, tile_extent, tile_id_from_row, tile_id_from_xyz, AsConnection, Compression, DedupStats,
+    FileFormat, Fnv1aHasher, Metadata, MetadataWarning, TileHasher, ZoomLevel, EMPTY_TILE_ID, MBTILES_APPLICATION_ID,
+};
+#[cfg(feature = "compression")]
+use crate::common::{gzip, UtfGrid};
+#[cfg(feature = "image")]
+use crate::read::get_metadata_value;
+use crate::read::{dedup_stats, read_empty_tile, read_metadata, table_exists, tiles, tiles_in_zoom_range};
 
 /// Creates the `metadata` table.
+///
+/// Fails if the table already exists, see [`create_metadata_table_if_not_exists`] for a variant that doesn't.
 pub fn create_metadata_table(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_metadata_table(tr, "")
+}
+
+/// Creates the `metadata` table unless it already exists.
+pub fn create_metadata_table_if_not_exists(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_metadata_table(tr, "IF NOT EXISTS")
+}
+
+fn execute_create_metadata_table(tr: &Transaction, condition: &str) -> rusqlite::Result<()> {
     tr.execute(
-        "CREATE TABLE metadata (
-            name TEXT,
-            value TEXT
-        )",
+        &format!(
+            "CREATE TABLE {} metadata (
+                name TEXT,
+                value TEXT
+            )",
+            condition
+        ),
         [],
     )?;
     Ok(())
 }
 
 /// Creates the `tiles` table.
+///
+/// Fails if the table already exists, see [`create_tiles_table_if_not_exists`] for a variant that doesn't.
 pub fn create_tiles_table(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_tiles_table(tr, "")
+}
+
+/// Creates the `tiles` table unless it (or a `tiles` view of a deduplicated database) already exists.
+pub fn create_tiles_table_if_not_exists(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_tiles_table(tr, "IF NOT EXISTS")
+}
+
+fn execute_create_tiles_table(tr: &Transaction, condition: &str) -> rusqlite::Result<()> {
+    tr.execute(
+        &format!(
+            "CREATE TABLE {} tiles (
+                zoom_level INTEGER,
+                tile_column INTEGER,
+                tile_row INTEGER,
+                tile_data BLOB
+            )",
+            condition
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Creates the `tiles` table as a `WITHOUT ROWID` table, clustered by its `(zoom_level, tile_column, tile_row)` primary
+/// key.
+///
+/// Compared to [`create_tiles_table`] with a separate [`create_tile_index`], tiles are stored in the primary key
+/// B-tree itself, which saves the space of the index and keeps tiles of the same area close to each other. On the
+/// other hand, inserting tiles out of key order is slower, large tiles (several kilobytes or more) are stored less
+/// efficiently than in a rowid table, and the tiles have no `rowid` (see
+/// [`tile_rowid`](crate::read::tile_rowid)). Don't call [`create_tile_index`] for such a table.
+pub fn create_tiles_table_without_rowid(tr: &Transaction) -> rusqlite::Result<()> {
     tr.execute(
         "CREATE TABLE tiles (
             zoom_level INTEGER,
             tile_column INTEGER,
             tile_row INTEGER,
-            tile_data BLOB
-        )",
+            tile_data BLOB,
+            PRIMARY KEY (zoom_level, tile_column, tile_row)
+        ) WITHOUT ROWID",
         [],
     )?;
     Ok(())
 }
 
 /// Creates the optional `grids` and `grid_data` tables.
+///
+/// Fails if either table already exists, see [`create_grid_tables_if_not_exists`] for a variant that doesn't.
 pub fn create_grid_tables(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_grid_tables(tr, "")
+}
+
+/// Creates the optional `grids` and `grid_data` tables, skipping the ones that already exist.
+pub fn create_grid_tables_if_not_exists(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_grid_tables(tr, "IF NOT EXISTS")
+}
+
+fn execute_create_grid_tables(tr: &Transaction, condition: &str) -> rusqlite::Result<()> {
     tr.execute(
-        "CREATE TABLE grids (
-            zoom_level INTEGER,
-            tile_column INTEGER,
-            tile_row INTEGER,
-            grid BLOB
-        )",
+        &format!(
+            "CREATE TABLE {} grids (
+                zoom_level INTEGER,
+                tile_column INTEGER,
+                tile_row INTEGER,
+                grid BLOB
+            )",
+            condition
+        ),
         [],
     )?;
     tr.execute(
-        "CREATE TABLE grid_data (
-            zoom_level INTEGER,
-            tile_column INTEGER,
-            tile_row INTEGER,
-            key_name TEXT,
-            key_json TEXT
-        )",
+        &format!(
+            "CREATE TABLE {} grid_data (
+                zoom_level INTEGER,
+                tile_column INTEGER,
+                tile_row INTEGER,
+                key_name TEXT,
+                key_json TEXT
+            )",
+            condition
+        ),
         [],
     )?;
     Ok(())
 }
 
 /// Creates the optional `tile_index` index for fast tile data lookup.
+///
+/// Fails if the index already exists, see [`create_tile_index_if_not_exists`] for a variant that doesn't.
 pub fn create_tile_index(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_tile_index(tr, "")
+}
+
+/// Creates the optional `tile_index` index unless it already exists.
+pub fn create_tile_index_if_not_exists(tr: &Transaction) -> rusqlite::Result<()> {
+    execute_create_tile_index(tr, "IF NOT EXISTS")
+}
+
+fn execute_create_tile_index(tr: &Transaction, condition: &str) -> rusqlite::Result<()> {
     tr.execute(
-        "CREATE UNIQUE INDEX tile_index ON tiles (
-            zoom_level, 
-            tile_column, 
-            tile_row
-        )",
+        &format!(
+            "CREATE UNIQUE INDEX {} tile_index ON tiles (
+                zoom_level,
+                tile_column,
+                tile_row
+            )",
+            condition
+        ),
         [],
     )?;
     Ok(())
 }
 
+/// Recreates the `tile_index` index, e.g. after a bulk ingest without it, returning the coordinates of the tiles which
+/// were stored more than once.
+///
+/// Of duplicate tiles only the last written one (with the highest `rowid`) is kept, as the unique index can't be
+/// created otherwise. Only databases with a flat `tiles` table are supported.
+pub fn rebuild_tile_index(tr: &Transaction) -> rusqlite::Result<Vec<TmsTileId>> {
+    let mut select_duplicates = tr.prepare(
+        "SELECT zoom_level, tile_column, tile_row FROM tiles
+        GROUP BY zoom_level, tile_column, tile_row HAVING COUNT(*) > 1",
+    )?;
+    let duplicates = select_duplicates
+        .query_map([], |row| {
+            tile_id_from_row(row.get(0)?, row.get(1)?, row.get(2)?).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Integer, Box::new(err))
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    tr.execute(
+        "DELETE FROM tiles WHERE rowid NOT IN (
+            SELECT MAX(rowid) FROM tiles GROUP BY zoom_level, tile_column, tile_row
+        )",
+        [],
+    )?;
+
+    tr.execute("DROP INDEX IF EXISTS tile_index", [])?;
+    create_tile_index(tr)?;
+
+    Ok(duplicates)
+}
+
 /// Sets the officially assigned MBTiles magic number as application ID for the database.
 pub fn set_application_id(tr: &Transaction) -> rusqlite::Result<()> {
-    const MBTILES_ID: i32 = 0x4d504258;
-    tr.execute(format!("PRAGMA application_id = {}", MBTILES_ID).as_str(), [])?;
+    tr.execute(
+        format!("PRAGMA application_id = {}", MBTILES_APPLICATION_ID).as_str(),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Creates a new MBTiles database at the given path.
+///
+/// Sets the application ID and creates the `metadata` and `tiles` tables along with the `tile_index` index, and
+/// optionally the grid tables, in a single transaction.
+pub fn create_mbtiles<P: AsRef<Path>>(path: P, include_grids: bool) -> rusqlite::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+
+    let tr = conn.transaction()?;
+
+    set_application_id(&tr)?;
+
+    create_metadata_table(&tr)?;
+    create_tiles_table(&tr)?;
+    if include_grids {
+        create_grid_tables(&tr)?;
+    }
+
+    create_tile_index(&tr)?;
+
+    tr.commit()?;
+
+    Ok(conn)
+}
+
+/// Sets how long to wait for locks held by other connections before failing with `SQLITE_BUSY`.
+///
+/// When one process writes a database while others read it (e.g. ingesting while serving), the recommended setup is
+/// to switch the database to write-ahead logging once with `PRAGMA journal_mode = WAL`, which lets readers proceed
+/// during writes, and to set a busy timeout on every connection to wait out the remaining short exclusive locks.
+pub fn set_busy_timeout(conn: &Connection, timeout: Duration) -> rusqlite::Result<()> {
+    conn.busy_timeout(timeout)
+}
+
+/// Error for metadata rejected by [`write_metadata_checked`], with the problems found by [`Metadata::validate`].
+#[derive(Debug)]
+pub struct InvalidMetadata(pub Vec<MetadataWarning>);
+
+impl fmt::Display for InvalidMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid metadata: ")?;
+        for (i, warning) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidMetadata {}
+
+/// Writes the given metadata into the database like [`write_metadata`], but fails with [`InvalidMetadata`] without
+/// writing anything if [`Metadata::validate`] finds any problems, e.g. an empty `name`.
+pub fn write_metadata_checked(tr: &Transaction, metadata: &Metadata) -> Result<(), Box<dyn std::error::Error>> {
+    metadata.validate().map_err(InvalidMetadata)?;
+    write_metadata(tr, metadata)
+}
+
+/// Writes the given metadata into the database like [`write_metadata`], storing the `custom` rows as a single
+/// `custom_json` row containing a JSON object.
+///
+/// This is faster and keeps the `metadata` table tidy for tilesets with many custom rows. The object is read back into
+/// [`Metadata::custom`] by [`read_metadata`], but other tools only see the single row.
+pub fn write_metadata_with_custom_json(
+    tr: &Transaction,
+    metadata: &Metadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_metadata(tr, metadata)?;
+    if !metadata.custom.is_empty() {
+        let mut insert_metadata = tr.prepare_cached("INSERT INTO metadata (name, value) VALUES (?1, ?2)")?;
+        insert_metadata.execute(params!["custom_json", serde_json::to_string(&metadata.custom)?])?;
+    }
     Ok(())
 }
 
 /// Writes the given metadata into the database.
-pub fn write_metadata(tr: &Transaction, metadata: Metadata) -> Result<(), Box<dyn std::error::Error>> {
+///
+/// Only the stored [`Metadata::compression`] is written, never the [`Metadata::inferred_compression`].
+///
+/// Fails with [`InvalidMetadata`] without writing anything if the bounds have no area, which viewers can't handle,
+/// see [`MetadataWarning::DegenerateBounds`].
+pub fn write_metadata(tr: &Transaction, metadata: &Metadata) -> Result<(), Box<dyn std::error::Error>> {
+    if metadata.has_degenerate_bounds() {
+        return Err(Box::new(InvalidMetadata(vec![MetadataWarning::DegenerateBounds])));
+    }
+
     let mut insert_metadata = tr.prepare_cached("INSERT INTO metadata (name, value) VALUES (?1, ?2)")?;
 
     insert_metadata.execute(params!["name", metadata.name])?;
 
-    if let FileFormat::Pbf(mvt_metadata) = &metadata.format {
-        insert_metadata.execute(params!["json", serde_json::to_string(&mvt_metadata)?])?;
+    match &metadata.format {
+        FileFormat::Pbf(mvt_metadata) => {
+            insert_metadata.execute(params!["json", serde_json::to_string(&mvt_metadata)?])?;
+        }
+        FileFormat::Jpg | FileFormat::Png | FileFormat::Webp => {
+            let json = match &metadata.raster_json {
+                Some(raster_json) => Some(raster_json.merge_into(metadata.raw_json.as_deref())?),
+                None => metadata.raw_json.clone(),
+            };
+            if let Some(json) = json {
+                insert_metadata.execute(params!["json", json])?;
+            }
+        }
+        FileFormat::Other(_) => {
+            if let Some(json) = &metadata.raw_json {
+                insert_metadata.execute(params!["json", json])?;
+            }
+        }
     }
 
-    let format_str: String = metadata.format.into();
-    insert_metadata.execute(params!["format", format_str])?;
+    insert_metadata.execute(params!["format", metadata.format.as_str()])?;
+
+    let corners = match (&metadata.bounds, &metadata.antimeridian_bounds) {
+        (Some(bounds), _) => Some((bounds.top_left(), bounds.bottom_right())),
+        (None, Some((west, east))) => Some((west.top_left(), east.bottom_right())),
+        (None, None) => None,
+    };
 
-    if let Some(bounds) = &metadata.bounds {
-        let tl = bounds.top_left();
-        let br = bounds.bottom_right();
+    if let Some((tl, br)) = corners {
         insert_metadata.execute(params![
             "bounds",
-            format!("{},{},{},{}", tl.lon(), br.lat(), br.lon(), tl.lat())
+            format!(
+                "{},{},{},{}",
+                format_degrees(tl.lon()),
+                format_degrees(br.lat()),
+                format_degrees(br.lon()),
+                format_degrees(tl.lat())
+            )
         ])?;
     }
 
     if let Some(center) = &metadata.center {
         let (coord, zoom) = center;
-        insert_metadata.execute(params!["center", format!("{},{},{}", coord.lon(), coord.lat(), zoom)])?;
+        insert_metadata.execute(params![
+            "center",
+            format!(
+                "{},{},{}",
+                format_degrees(coord.lon()),
+                format_degrees(coord.lat()),
+                zoom
+            )
+        ])?;
+    }
+
+    if let Some(minzoom) = &metadata.minzoom {
+        insert_metadata.execute(params!["minzoom", minzoom])?;
     }
 
-    if let Some(zoom_range) = &metadata.zoom_range {
-        insert_metadata.execute(params!["minzoom", zoom_range.start()])?;
-        insert_metadata.execute(params!["maxzoom", zoom_range.end()])?;
+    if let Some(maxzoom) = &metadata.maxzoom {
+        insert_metadata.execute(params!["maxzoom", maxzoom])?;
     }
 
     if let Some(attribution) = &metadata.attribution {
@@ -116,40 +369,730 @@ pub fn write_metadata(tr: &Transaction, metadata: Metadata) -> Result<(), Box<dy
         insert_metadata.execute(params!["description", description])?;
     }
 
-    if let Some(r#type) = metadata.r#type {
-        let type_str: &'static str = r#type.into();
-        insert_metadata.execute(params!["type", type_str])?;
+    if let Some(r#type) = &metadata.r#type {
+        insert_metadata.execute(params!["type", r#type.as_str()])?;
     }
 
     if let Some(version) = &metadata.version {
         insert_metadata.execute(params!["version", version])?;
     }
 
+    if let Some(generator) = &metadata.generator {
+        insert_metadata.execute(params!["generator", generator])?;
+    }
+
+    if let Some(generator_options) = &metadata.generator_options {
+        insert_metadata.execute(params!["generator_options", generator_options])?;
+    }
+
+    if let Some(scheme) = &metadata.scheme {
+        insert_metadata.execute(params!["scheme", scheme.as_str()])?;
+    }
+
+    if let Some(compression) = &metadata.compression {
+        insert_metadata.execute(params!["compression", compression.as_str()])?;
+    }
+
+    Ok(())
+}
+
+/// Sets the value of a single metadata row with the given name, inserting the row if it doesn't exist yet.
+pub fn set_metadata_value(tr: &Transaction, name: &str, value: &str) -> rusqlite::Result<()> {
+    let mut update_metadata = tr.prepare_cached("UPDATE metadata SET value = ?2 WHERE name = ?1")?;
+    if update_metadata.execute(params![name, value])? == 0 {
+        let mut insert_metadata = tr.prepare_cached("INSERT INTO metadata (name, value) VALUES (?1, ?2)")?;
+        insert_metadata.execute(params![name, value])?;
+    }
     Ok(())
 }
 
+/// Sets the `name` of the tileset, e.g. for a derived copy of a database, inserting the row if it doesn't exist yet.
+///
+/// Other rows like `description` can be updated the same way with [`set_metadata_value`].
+pub fn rename_tileset(tr: &Transaction, new_name: &str) -> rusqlite::Result<()> {
+    set_metadata_value(tr, "name", new_name)
+}
+
+/// Formats degrees with 6 decimal places (about 10 cm precision), omitting trailing zeros.
+fn format_degrees(degrees: f64) -> String {
+    let formatted = format!("{:.6}", degrees);
+    match formatted.trim_end_matches('0').trim_end_matches('.') {
+        "-0" => "0".to_owned(),
+        trimmed => trimmed.to_owned(),
+    }
+}
+
 /// Writes the given tile data into the database.
 ///
+/// Tile IDs with a zoom level above [`MAX_ZOOM_LEVEL`](crate::common::MAX_ZOOM_LEVEL) or a column or row out of range
+/// for the zoom level are rejected with a [`ToSqlConversionFailure`](rusqlite::Error::ToSqlConversionFailure) error.
+///
 /// **Note:** `tile_data` must be GZIP-compressed if Mapbox Vector Tile PBF is being stored.
 pub fn write_tile(tr: &Transaction, tile_id: TmsTileId, tile_data: Vec<u8>) -> rusqlite::Result<()> {
+    insert_tile(tr, tile_id, &tile_data)
+}
+
+/// Writes the given tile data like [`write_tile`], without taking ownership of it.
+fn insert_tile(tr: &Transaction, tile_id: TmsTileId, tile_data: &[u8]) -> rusqlite::Result<()> {
+    check_tile_id(&tile_id)?;
     let mut insert_tile =
         tr.prepare_cached("INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)")?;
     insert_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), tile_data])?;
     Ok(())
 }
 
+/// Compresses the given tile data with the given compression and writes it, see [`write_tile`].
+#[cfg(feature = "compression")]
+pub fn write_tile_compressed(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    tile_data: &[u8],
+    compression: Compression,
+) -> rusqlite::Result<()> {
+    write_tile(tr, tile_id, compression.compress(tile_data))
+}
+
+/// Compresses the given tile data with the given compression and level and writes it, see
+/// [`Compression::compress_with_level`] and [`write_tile`].
+///
+/// Lower levels suit live updates, while final archive builds benefit from the highest ones.
+#[cfg(feature = "compression")]
+pub fn write_tile_compressed_with_level(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    tile_data: &[u8],
+    compression: Compression,
+    level: u32,
+) -> rusqlite::Result<()> {
+    write_tile(tr, tile_id, compression.compress_with_level(tile_data, level))
+}
+
+/// The effect of writing a tile with [`upsert_tile`] or [`write_tile_or_ignore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// No tile existed at the coordinates, so a new one was inserted.
+    Inserted,
+    /// The data of the existing tile at the coordinates was replaced.
+    Replaced,
+    /// A tile existed at the coordinates, so nothing was written.
+    Ignored,
+}
+
+/// Writes the given tile, replacing the data of the existing tile at the same coordinates if there's one.
+///
+/// Unlike `INSERT OR REPLACE`, this doesn't require the unique [`tile_index`](create_tile_index).
+pub fn upsert_tile(tr: &Transaction, tile_id: TmsTileId, tile_data: Vec<u8>) -> rusqlite::Result<WriteOutcome> {
+    check_tile_id(&tile_id)?;
+    let mut update_tile = tr.prepare_cached(
+        "UPDATE tiles SET tile_data = ?4 WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+    )?;
+    if update_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), &tile_data])? > 0 {
+        return Ok(WriteOutcome::Replaced);
+    }
+    write_tile(tr, tile_id, tile_data)?;
+    Ok(WriteOutcome::Inserted)
+}
+
+/// Writes the given tile unless a tile exists at the same coordinates.
+///
+/// **Note:** existing tiles are only detected with the unique [`tile_index`](create_tile_index), without it the tile
+/// is always inserted.
+pub fn write_tile_or_ignore(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    tile_data: Vec<u8>,
+) -> rusqlite::Result<WriteOutcome> {
+    check_tile_id(&tile_id)?;
+    let mut insert_tile = tr.prepare_cached(
+        "INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    if insert_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), tile_data])? > 0 {
+        Ok(WriteOutcome::Inserted)
+    } else {
+        Ok(WriteOutcome::Ignored)
+    }
+}
+
+/// Writes the tile with the given XYZ scheme coordinates, as used by web map tile URLs, see [`write_tile`].
+///
+/// Invalid coordinates result in a [`ToSqlConversionFailure`](rusqlite::Error::ToSqlConversionFailure) error.
+pub fn write_tile_xyz(tr: &Transaction, z: u32, x: u32, y: u32, tile_data: Vec<u8>) -> rusqlite::Result<()> {
+    write_tile(tr, xyz_tile_id(z, x, y)?, tile_data)
+}
+
+/// Writes the given tiles into the database, returning the number of written tiles.
+///
+/// **Note:** tile data must be GZIP-compressed if Mapbox Vector Tile PBF is being stored.
+pub fn write_tiles<I>(tr: &Transaction, tiles: I) -> rusqlite::Result<u64>
+where
+    I: IntoIterator<Item = (TmsTileId, Vec<u8>)>,
+{
+    write_tiles_with_progress(tr, tiles, u64::MAX, &mut |_| {})
+}
+
+/// Writes the given tiles into the database like [`write_tiles`], reporting progress.
+///
+/// `progress` is called with the number of tiles written so far after every `interval` tiles, and once more after the
+/// last tile if it wasn't reported yet. The callback can't abort writing: to stop early, end the `tiles` iterator
+/// (e.g. with [`Iterator::take_while`]), then drop the transaction instead of committing it to discard the tiles.
+pub fn write_tiles_with_progress<I>(
+    tr: &Transaction,
+    tiles: I,
+    interval: u64,
+    progress: &mut dyn FnMut(u64),
+) -> rusqlite::Result<u64>
+where
+    I: IntoIterator<Item = (TmsTileId, Vec<u8>)>,
+{
+    let interval = interval.max(1);
+    let mut count = 0;
+
+    for (tile_id, tile_data) in tiles {
+        write_tile(tr, tile_id, tile_data)?;
+        count += 1;
+        if count % interval == 0 {
+            progress(count);
+        }
+    }
+
+    if count % interval != 0 {
+        progress(count);
+    }
+
+    Ok(count)
+}
+
+/// Encodes the image in the given raster format and writes it as the given tile.
+///
+/// Only the PNG and JPEG formats are supported. If the `format` metadata is absent, it's set to the matching format.
+#[cfg(feature = "image")]
+pub fn write_tile_image(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    image: &image::DynamicImage,
+    format: image::ImageOutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format_str = match &format {
+        image::ImageOutputFormat::Png => "png",
+        image::ImageOutputFormat::Jpeg(_) => "jpg",
+        _ => return Err("unsupported tile image format".into()),
+    };
+
+    let mut tile_data = Vec::new();
+    image.write_to(&mut tile_data, format)?;
+    write_tile(tr, tile_id, tile_data)?;
+
+    if get_metadata_value(tr, "format")?
+        .filter(|format| !format.is_empty())
+        .is_none()
+    {
+        set_metadata_value(tr, "format", format_str)?;
+    }
+
+    Ok(())
+}
+
+/// Error of [`write_tiles_fallible`].
+#[derive(Debug)]
+pub enum WriteError<E> {
+    /// The tile iterator yielded an error.
+    Source(E),
+    /// Writing a tile failed.
+    Sqlite(rusqlite::Error),
+}
+
+impl<E: fmt::Display> fmt::Display for WriteError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Source(err) => write!(f, "failed to produce tile: {}", err),
+            WriteError::Sqlite(err) => write!(f, "failed to write tile: {}", err),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for WriteError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::Source(err) => Some(err),
+            WriteError::Sqlite(err) => Some(err),
+        }
+    }
+}
+
+impl<E> From<rusqlite::Error> for WriteError<E> {
+    fn from(err: rusqlite::Error) -> Self {
+        WriteError::Sqlite(err)
+    }
+}
+
+/// Writes the tiles of the given fallible iterator into the database, returning the number of written tiles.
+///
+/// Stops at the first error of the iterator, in which case the transaction should be rolled back to discard the
+/// tiles written so far, see [`write_tiles`].
+pub fn write_tiles_fallible<I, E>(tr: &Transaction, tiles: I) -> Result<u64, WriteError<E>>
+where
+    I: IntoIterator<Item = Result<(TmsTileId, Vec<u8>), E>>,
+{
+    let mut count = 0;
+    for tile in tiles {
+        let (tile_id, tile_data) = tile.map_err(WriteError::Source)?;
+        write_tile(tr, tile_id, tile_data)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Writes tiles into a database across multiple transactions, committing every given number of tiles.
+///
+/// This keeps long ingests robust: if the process is interrupted, the tiles of the already committed batches are kept.
+/// Tiles are buffered until the batch is full, then written and committed in a single transaction.
+///
+/// ```no_run
+/// # use rosm_mbtiles::write::{create_mbtiles, TileWriter};
+/// # use rosm_mbtiles::common::Metadata;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut writer = TileWriter::new(create_mbtiles("tiles.mbtiles", false)?, 10_000);
+/// # let tiles = Vec::new();
+/// for (tile_id, tile_data) in tiles {
+///     writer.put(tile_id, tile_data)?;
+/// }
+/// let conn = writer.finish(&Metadata::default())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// **Note:** buffered tiles are discarded if the writer is dropped without calling [`finish`](TileWriter::finish).
+pub struct TileWriter {
+    conn: Connection,
+    batch_size: usize,
+    batch: Vec<(TmsTileId, Vec<u8>)>,
+    written: u64,
+}
+
+impl TileWriter {
+    /// Creates a writer for the given database, which must already have the `metadata` and `tiles` tables, see
+    /// [`create_mbtiles`].
+    pub fn new(conn: Connection, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        TileWriter {
+            conn,
+            batch_size,
+            batch: Vec::with_capacity(batch_size),
+            written: 0,
+        }
+    }
+
+    /// Adds the given tile, committing the batch if it's full, see [`write_tile`].
+    pub fn put(&mut self, tile_id: TmsTileId, tile_data: Vec<u8>) -> rusqlite::Result<()> {
+        self.batch.push((tile_id, tile_data));
+        if self.batch.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes and commits the buffered tiles.
+    ///
+    /// The tiles are only removed from the buffer once they're committed, so if writing fails, e.g. because the
+    /// database is busy, the batch is kept and flushing can be retried.
+    pub fn flush(&mut self) -> rusqlite::Result<()> {
+        let tr = self.conn.transaction()?;
+        Self::write_batch(&tr, &self.batch)?;
+        tr.commit()?;
+
+        self.written += self.batch.len() as u64;
+        self.batch.clear();
+        Ok(())
+    }
+
+    /// Returns the number of committed tiles.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Commits the remaining tiles along with the given metadata, and returns the connection.
+    pub fn finish(mut self, metadata: &Metadata) -> Result<Connection, Box<dyn std::error::Error>> {
+        let tr = self.conn.transaction()?;
+        Self::write_batch(&tr, &self.batch)?;
+        write_metadata(&tr, metadata)?;
+        tr.commit()?;
+        Ok(self.conn)
+    }
+
+    fn write_batch(tr: &Transaction, batch: &[(TmsTileId, Vec<u8>)]) -> rusqlite::Result<()> {
+        for (tile_id, tile_data) in batch {
+            insert_tile(tr, *tile_id, tile_data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transforms every tile in the database with the given function, e.g. to re-encode the tile data.
+///
+/// The function receives each tile and returns its new data, or `None` to delete the tile. Tiles are read in batches
+/// so that no read cursor is open while the rows are updated, and the whole database is never loaded into memory.
+/// Returns the number of updated and deleted tiles.
+///
+/// References to the canonical empty tile are passed to the function resolved, see [`write_empty_tile_ref`]. If the
+/// function returns the empty tile unchanged, the tile is kept as a reference.
+///
+/// **Note:** only databases storing each tile separately are supported, as the `tiles` view of the deduplicating layout
+/// can't be updated.
+pub fn transform_tiles<F>(tr: &Transaction, mut f: F) -> rusqlite::Result<(u64, u64)>
+where
+    F: FnMut(TmsTileId, Vec<u8>) -> Option<Vec<u8>>,
+{
+    let mut update_tile = tr.prepare_cached(
+        "UPDATE tiles SET tile_data = ?4 WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+    )?;
+    let mut delete_tile =
+        tr.prepare_cached("DELETE FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
+
+    let empty_tile = read_empty_tile(tr)?;
+    let (mut updated, mut deleted) = (0, 0);
+
+    for tile in tiles(tr) {
+        let (tile_id, tile_data) = tile?;
+
+        match f(tile_id, tile_data) {
+            Some(tile_data) => {
+                let tile_data = if empty_tile.as_ref() == Some(&tile_data) {
+                    Vec::new()
+                } else {
+                    tile_data
+                };
+                update_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), tile_data])?;
+                updated += 1;
+            }
+            None => {
+                delete_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+                deleted += 1;
+            }
+        }
+    }
+
+    Ok((updated, deleted))
+}
+
+/// Writes tiles copied from another database, keeping references to the canonical empty tile as references instead of
+/// storing a copy of the empty tile for each of them.
+struct EmptyTileRefs {
+    /// The empty tile of the source, unless the destination already has a different one.
+    empty_tile: Option<Vec<u8>>,
+    /// Whether the destination has an empty tile set.
+    is_set: bool,
+}
+
+impl EmptyTileRefs {
+    fn new(src: &impl AsConnection, dst: &Transaction) -> rusqlite::Result<Self> {
+        let empty_tile = read_empty_tile(src)?;
+        Ok(match read_empty_tile(dst)? {
+            Some(dst_empty_tile) => EmptyTileRefs {
+                empty_tile: empty_tile.filter(|empty_tile| *empty_tile == dst_empty_tile),
+                is_set: true,
+            },
+            None => EmptyTileRefs {
+                empty_tile,
+                is_set: false,
+            },
+        })
+    }
+
+    /// Writes the given tile, or a reference to the empty tile if the tile is the empty tile of the source.
+    fn write_tile(&mut self, tr: &Transaction, tile_id: TmsTileId, tile_data: Vec<u8>) -> rusqlite::Result<()> {
+        match &self.empty_tile {
+            Some(empty_tile) if *empty_tile == tile_data => {
+                if !self.is_set {
+                    set_empty_tile(tr, tile_data)?;
+                    self.is_set = true;
+                }
+                write_empty_tile_ref(tr, tile_id)
+            }
+            _ => write_tile(tr, tile_id, tile_data),
+        }
+    }
+}
+
+/// Copies the tiles within the given zoom levels from the source database into the destination, along with the
+/// metadata, returning the number of copied tiles.
+///
+/// The `bounds` and zoom levels of the destination metadata are recomputed from the copied tiles, and the zoom level
+/// of the `center` is clamped into the new zoom levels. The destination must already have the `metadata` and `tiles`
+/// tables, see [`create_mbtiles`].
+///
+/// References to the empty tile of the source are copied as references, setting the same empty tile in the
+/// destination, unless the destination already has a different one, see [`set_empty_tile`].
+pub fn extract_zoom_range(
+    src: &impl AsConnection,
+    dst: &Transaction,
+    zoom_range: RangeInclusive<ZoomLevel>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut metadata = read_metadata(src)?;
+
+    let mut copied = 0;
+    let mut extent = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    let mut zooms = (u32::MAX, u32::MIN);
+    let mut empty_tile_refs = EmptyTileRefs::new(src, dst)?;
+
+    for tile in tiles_in_zoom_range(src, zoom_range) {
+        let (tile_id, tile_data) = tile?;
+        empty_tile_refs.write_tile(dst, tile_id, tile_data)?;
+        copied += 1;
+
+        let (west, south, east, north) = tile_extent(tile_id.z(), tile_id.x(), tile_id.y());
+        extent = (
+            extent.0.min(west),
+            extent.1.min(south),
+            extent.2.max(east),
+            extent.3.max(north),
+        );
+        zooms = (zooms.0.min(tile_id.z()), zooms.1.max(tile_id.z()));
+    }
+
+    if copied > 0 {
+        let (west, south, east, north) = extent;
+        let tl = GeoCoord::from_degrees(west, north).map_err(|_| "tile extent out of range")?;
+        let br = GeoCoord::from_degrees(east, south).map_err(|_| "tile extent out of range")?;
+        metadata.bounds = Some(GeoRect::new(tl, br).map_err(|_| "tile extent out of order")?);
+        metadata.antimeridian_bounds = None;
+        metadata.minzoom = Some(zooms.0);
+        metadata.maxzoom = Some(zooms.1);
+        if let Some((_, zoom)) = &mut metadata.center {
+            *zoom = (*zoom).max(zooms.0).min(zooms.1);
+        }
+    } else {
+        metadata.bounds = None;
+        metadata.antimeridian_bounds = None;
+        metadata.minzoom = None;
+        metadata.maxzoom = None;
+    }
+
+    write_metadata(dst, &metadata)?;
+
+    Ok(copied)
+}
+
+/// Applies a patch database to the tiles of the base database, returning the number of written and deleted tiles.
+///
+/// Every tile of the patch replaces the tile at the same coordinates in the base, then the tiles at the coordinates
+/// of `delete_list` are deleted. Afterwards the `minzoom` and `maxzoom` metadata rows are updated to the zoom levels
+/// of the remaining tiles. Only databases with a flat `tiles` table are supported.
+///
+/// References to the empty tile of the patch are written as references, like in [`extract_zoom_range`].
+pub fn apply_patch(
+    base: &Transaction,
+    patch: &impl AsConnection,
+    delete_list: &[TmsTileId],
+) -> rusqlite::Result<(u64, u64)> {
+    let mut delete_tile =
+        base.prepare_cached("DELETE FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
+
+    let (mut written, mut deleted) = (0, 0);
+    let mut empty_tile_refs = EmptyTileRefs::new(patch, base)?;
+
+    for tile in tiles(patch) {
+        let (tile_id, tile_data) = tile?;
+        delete_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])?;
+        empty_tile_refs.write_tile(base, tile_id, tile_data)?;
+        written += 1;
+    }
+
+    for tile_id in delete_list {
+        deleted += delete_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])? as u64;
+    }
+
+    let zooms: (Option<u32>, Option<u32>) =
+        base.query_row("SELECT MIN(zoom_level), MAX(zoom_level) FROM tiles", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+
+    if let (Some(minzoom), Some(maxzoom)) = zooms {
+        set_metadata_value(base, "minzoom", &minzoom.to_string())?;
+        set_metadata_value(base, "maxzoom", &maxzoom.to_string())?;
+    } else {
+        base.execute("DELETE FROM metadata WHERE name IN ('minzoom', 'maxzoom')", [])?;
+    }
+
+    Ok((written, deleted))
+}
+
+/// Writes the given tile into a database using the deduplicating `map`/`images` layout, keying the tile data with
+/// the given hasher, see [`Fnv1aHasher`] for the default one.
+///
+/// The tile data is only stored if no tile with the same hash exists yet. The hasher must match the one the database
+/// was deduplicated with, otherwise identical tiles are stored twice.
+pub fn write_tile_dedup(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    tile_data: Vec<u8>,
+    hasher: &impl TileHasher,
+) -> rusqlite::Result<()> {
+    check_tile_id(&tile_id)?;
+    let hash = hasher.hash(&tile_data);
+    let mut insert_image = tr.prepare_cached("INSERT OR IGNORE INTO images (tile_data, tile_id) VALUES (?1, ?2)")?;
+    insert_image.execute(params![tile_data, hash])?;
+    let mut insert_map =
+        tr.prepare_cached("INSERT INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?4)")?;
+    insert_map.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), hash])?;
+    Ok(())
+}
+
+/// Sets the canonical empty tile, e.g. a fully transparent image, which the tiles written with
+/// [`write_empty_tile_ref`] resolve to.
+///
+/// In the deduplicating `map`/`images` layout, it's stored in the `images` table under [`EMPTY_TILE_ID`], otherwise
+/// in a separate single-row `empty_tile` table. Setting it again replaces it for all references.
+///
+/// **Note:** once an empty tile is set outside of the deduplicating layout, every zero-length tile is treated as a
+/// reference to it, as that's how references are stored.
+pub fn set_empty_tile(tr: &Transaction, tile_data: Vec<u8>) -> rusqlite::Result<()> {
+    if table_exists(tr, "map")? {
+        tr.execute(
+            "INSERT OR REPLACE INTO images (tile_data, tile_id) VALUES (?1, ?2)",
+            params![tile_data, EMPTY_TILE_ID],
+        )?;
+    } else {
+        tr.execute_batch("CREATE TABLE IF NOT EXISTS empty_tile (tile_data BLOB); DELETE FROM empty_tile;")?;
+        tr.execute("INSERT INTO empty_tile (tile_data) VALUES (?1)", params![tile_data])?;
+    }
+    Ok(())
+}
+
+/// Writes a reference to the canonical empty tile instead of tile data, see [`set_empty_tile`].
+///
+/// The functions of this crate reading tile data, e.g. [`read_tile`](crate::read::read_tile) and [`tiles`], resolve
+/// the reference to the empty tile. Other tools see the references depending on the layout:
+/// - In the deduplicating `map`/`images` layout, the reference is a regular `map` row pointing to the empty tile, so
+///   all tools reading the `tiles` view see the empty tile.
+/// - Otherwise the reference is a zero-length `tile_data` blob, which most tile servers treat as a tile without
+///   content.
+pub fn write_empty_tile_ref(tr: &Transaction, tile_id: TmsTileId) -> rusqlite::Result<()> {
+    if !table_exists(tr, "map")? {
+        return write_tile(tr, tile_id, Vec::new());
+    }
+
+    check_tile_id(&tile_id)?;
+    let mut insert_map =
+        tr.prepare_cached("INSERT INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?4)")?;
+    insert_map.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), EMPTY_TILE_ID])?;
+    Ok(())
+}
+
+/// Converts a database storing each tile separately into the deduplicating layout, returning its statistics.
+///
+/// Distinct tile data blobs are moved into the `images` table keyed by their content hash, tile coordinates into the
+/// `map` table referencing them, then the `tiles` table is dropped and replaced with a view joining the two. As this
+/// happens in the given transaction, the database is left unchanged if it's not committed.
+///
+/// If the database already uses the deduplicating layout, it's left unchanged and its current statistics are returned.
+///
+/// **Note:** fails if the `tiles` table contains duplicate coordinates, see [`create_tile_index`].
+pub fn deduplicate(tr: &Transaction) -> rusqlite::Result<DedupStats> {
+    deduplicate_with(tr, &Fnv1aHasher)
+}
+
+/// Converts a database into the deduplicating layout like [`deduplicate`], keying the tile data with the given hasher.
+pub fn deduplicate_with(tr: &Transaction, hasher: &impl TileHasher) -> rusqlite::Result<DedupStats> {
+    if table_exists(tr, "map")? {
+        return dedup_stats(tr);
+    }
+
+    tr.execute_batch(
+        "CREATE TABLE images (
+            tile_data BLOB,
+            tile_id TEXT PRIMARY KEY
+        );
+        CREATE TABLE map (
+            zoom_level INTEGER,
+            tile_column INTEGER,
+            tile_row INTEGER,
+            tile_id TEXT
+        );",
+    )?;
+
+    let mut map_rows = 0;
+    let mut tile_bytes = 0;
+
+    {
+        let mut select_tiles = tr.prepare("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles")?;
+        let mut insert_image = tr.prepare("INSERT OR IGNORE INTO images (tile_data, tile_id) VALUES (?1, ?2)")?;
+        let mut insert_map =
+            tr.prepare("INSERT INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?4)")?;
+
+        let mut rows = select_tiles.query([])?;
+        while let Some(row) = rows.next()? {
+            let (z, x, y): (i64, i64, i64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+            let tile_data: Vec<u8> = row.get(3)?;
+            let tile_id = hasher.hash(&tile_data);
+
+            insert_image.execute(params![tile_data, tile_id])?;
+            insert_map.execute(params![z, x, y, tile_id])?;
+
+            map_rows += 1;
+            tile_bytes += tile_data.len() as u64;
+        }
+    }
+
+    tr.execute_batch(
+        "DROP TABLE tiles;
+        CREATE UNIQUE INDEX map_index ON map (
+            zoom_level,
+            tile_column,
+            tile_row
+        );
+        CREATE VIEW tiles AS SELECT
+            map.zoom_level AS zoom_level,
+            map.tile_column AS tile_column,
+            map.tile_row AS tile_row,
+            images.tile_data AS tile_data
+        FROM map JOIN images ON images.tile_id = map.tile_id;",
+    )?;
+
+    let (image_rows, image_bytes) = tr.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(tile_data)), 0) FROM images",
+        [],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+    )?;
+
+    Ok(DedupStats {
+        map_rows,
+        image_rows: image_rows as u64,
+        tile_bytes,
+        image_bytes: image_bytes as u64,
+    })
+}
+
 /// Writes [UTFGrid](https://github.com/mapbox/utfgrid-spec) grid for the given tile.
 ///
-/// **Note:** `grid` must be GZIP-compressed.
+/// Tile IDs are validated like in [`write_tile`].
+///
+/// **Note:** `grid` must be GZIP-compressed, otherwise a
+/// [`ToSqlConversionFailure`](rusqlite::Error::ToSqlConversionFailure) error is returned.
 pub fn write_grid(tr: &Transaction, tile_id: TmsTileId, grid: Vec<u8>) -> rusqlite::Result<()> {
+    check_tile_id(&tile_id)?;
+    if detect_compression(&grid) != Compression::Gzip {
+        return Err(rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "grid is not GZIP-compressed",
+        ))));
+    }
     let mut insert_grid =
         tr.prepare_cached("INSERT INTO grids (zoom_level, tile_column, tile_row, grid) VALUES (?1, ?2, ?3, ?4)")?;
     insert_grid.execute(params![tile_id.z(), tile_id.x(), tile_id.y(), grid])?;
     Ok(())
 }
 
+/// GZIP-compresses the given grid JSON and writes it, see [`write_grid`].
+#[cfg(feature = "compression")]
+pub fn write_grid_compressed(tr: &Transaction, tile_id: TmsTileId, raw_grid_json: &str) -> rusqlite::Result<()> {
+    write_grid(tr, tile_id, gzip(raw_grid_json.as_bytes()))
+}
+
 /// Writes [UTFGrid](https://github.com/mapbox/utfgrid-spec) data for the given tile and key.
+///
+/// Tile IDs are validated like in [`write_tile`].
 pub fn write_grid_data(tr: &Transaction, tile_id: TmsTileId, key: &str, data: &str) -> rusqlite::Result<()> {
+    check_tile_id(&tile_id)?;
     let mut insert_grid_data = tr.prepare_cached(
         "INSERT INTO grid_data (zoom_level, tile_column, tile_row, key_name, key_json) VALUES (?1, ?2, ?3, ?4, ?5)",
     )?;
@@ -157,11 +1100,767 @@ pub fn write_grid_data(tr: &Transaction, tile_id: TmsTileId, key: &str, data: &s
     Ok(())
 }
 
+/// Writes the grid with the given XYZ scheme coordinates, see [`write_grid`] and [`write_tile_xyz`].
+pub fn write_grid_xyz(tr: &Transaction, z: u32, x: u32, y: u32, grid: Vec<u8>) -> rusqlite::Result<()> {
+    write_grid(tr, xyz_tile_id(z, x, y)?, grid)
+}
+
+/// Writes the grid data for the given key and XYZ scheme coordinates, see [`write_grid_data`] and
+/// [`write_tile_xyz`].
+pub fn write_grid_data_xyz(tr: &Transaction, z: u32, x: u32, y: u32, key: &str, data: &str) -> rusqlite::Result<()> {
+    write_grid_data(tr, xyz_tile_id(z, x, y)?, key, data)
+}
+
+/// Converts XYZ scheme coordinates into a tile ID, see [`tile_id_from_xyz`].
+fn xyz_tile_id(z: u32, x: u32, y: u32) -> rusqlite::Result<TmsTileId> {
+    tile_id_from_xyz(z, x, y).map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+/// Checks that the given tile ID addresses a valid tile, see [`tile_id_from_row`].
+fn check_tile_id(tile_id: &TmsTileId) -> rusqlite::Result<()> {
+    tile_id_from_row(tile_id.z(), tile_id.x(), tile_id.y())
+        .map(|_| ())
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+/// Deletes the given tile, returning the number of deleted rows.
+///
+/// For databases using the deduplicating `map`/`images` layout, the row of the `map` table is deleted, leaving the
+/// tile data in the `images` table.
+pub fn delete_tile(tr: &Transaction, tile_id: TmsTileId) -> rusqlite::Result<usize> {
+    let table = if table_exists(tr, "map")? { "map" } else { "tiles" };
+    let mut delete_tile = tr.prepare_cached(&format!(
+        "DELETE FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+        table
+    ))?;
+    delete_tile.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])
+}
+
+/// Deletes the grid of the given tile, returning the number of deleted rows.
+///
+/// Databases without a `grids` table are left unchanged, see [`has_grids`](crate::read::has_grids).
+pub fn delete_grid(tr: &Transaction, tile_id: TmsTileId) -> rusqlite::Result<usize> {
+    if !table_exists(tr, "grids")? {
+        return Ok(0);
+    }
+    let mut delete_grid =
+        tr.prepare_cached("DELETE FROM grids WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
+    delete_grid.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])
+}
+
+/// Deletes the grid data of all keys of the given tile, returning the number of deleted rows.
+///
+/// Databases without a `grid_data` table are left unchanged.
+pub fn delete_grid_data(tr: &Transaction, tile_id: TmsTileId) -> rusqlite::Result<usize> {
+    if !table_exists(tr, "grid_data")? {
+        return Ok(0);
+    }
+    let mut delete_grid_data =
+        tr.prepare_cached("DELETE FROM grid_data WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3")?;
+    delete_grid_data.execute(params![tile_id.z(), tile_id.x(), tile_id.y()])
+}
+
+/// Deletes all tiles from the database, keeping the metadata and indexes intact.
+///
+/// For databases using the deduplicating `map`/`images` layout, both tables are cleared, except for the canonical
+/// empty tile, see [`set_empty_tile`]. Returns the number of deleted tiles.
+pub fn clear_tiles(tr: &Transaction) -> rusqlite::Result<usize> {
+    if table_exists(tr, "map")? {
+        let deleted = tr.execute("DELETE FROM map", [])?;
+        if table_exists(tr, "images")? {
+            tr.execute("DELETE FROM images WHERE tile_id IS NOT ?1", params![EMPTY_TILE_ID])?;
+        }
+        Ok(deleted)
+    } else {
+        tr.execute("DELETE FROM tiles", [])
+    }
+}
+
+/// Writes the given [UTFGrid](https://github.com/mapbox/utfgrid-spec) for the given tile.
+///
+/// The grid and its keys are GZIP-compressed into the `grids` table, while the data of each key is written into the
+/// `grid_data` table.
+#[cfg(feature = "compression")]
+pub fn write_utfgrid(
+    tr: &Transaction,
+    tile_id: TmsTileId,
+    utfgrid: &UtfGrid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize)]
+    struct GridJson<'a> {
+        grid: &'a [String],
+        keys: &'a [String],
+    }
+
+    let grid_json = serde_json::to_vec(&GridJson {
+        grid: &utfgrid.grid,
+        keys: &utfgrid.keys,
+    })?;
+    write_grid(tr, tile_id, gzip(&grid_json))?;
+
+    for (key, data) in &utfgrid.data {
+        write_grid_data(tr, tile_id, key, &serde_json::to_string(data)?)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod mbtiles_write_test {
     use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    use rusqlite::OpenFlags;
 
+    use super::*;
     use crate::common::{MvtMetadata, VectorLayer};
+    use crate::read::{
+        get_metadata_value, missing_tiles, read_grid_data, read_metadata_raw, read_overview_tile, read_tile,
+        read_tile_arc, read_tile_into, read_tiles, MbtilesReader, TileQuery,
+    };
+
+    #[test]
+    fn format_coordinate_degrees() {
+        assert_eq!(format_degrees(-180.0), "-180");
+        assert_eq!(format_degrees(85.05112877980659), "85.051129");
+        assert_eq!(format_degrees(0.1), "0.1");
+        assert_eq!(format_degrees(-0.0000001), "0");
+    }
+
+    #[test]
+    fn grid_data_round_trip() {
+        let tile_id = tile_id_from_row(1, 0, 1).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_grid_tables(&tr).unwrap();
+        write_grid_data(&tr, tile_id, "1", r#"{"name":"a"}"#).unwrap();
+        tr.commit().unwrap();
+
+        assert_eq!(
+            read_grid_data(&conn, tile_id, "1").unwrap().as_deref(),
+            Some(r#"{"name":"a"}"#)
+        );
+        assert_eq!(read_grid_data(&conn, tile_id, "2").unwrap(), None);
+    }
+
+    #[test]
+    fn create_tables_if_not_exists() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        for _ in 0..2 {
+            create_metadata_table_if_not_exists(&tr).unwrap();
+            create_tiles_table_if_not_exists(&tr).unwrap();
+            create_grid_tables_if_not_exists(&tr).unwrap();
+            create_tile_index_if_not_exists(&tr).unwrap();
+        }
+        assert!(create_metadata_table(&tr).is_err());
+        assert!(create_tiles_table(&tr).is_err());
+        assert!(create_grid_tables(&tr).is_err());
+        assert!(create_tile_index(&tr).is_err());
+    }
+
+    #[test]
+    fn bounds_and_center_round_trip() {
+        let bounds = GeoRect::new(
+            GeoCoord::from_degrees(-10.123456789, 50.5).unwrap(),
+            GeoCoord::from_degrees(20.5, -30.987654321).unwrap(),
+        )
+        .unwrap();
+        let center = GeoCoord::from_degrees(5.000000123, 10.25).unwrap();
+
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            format: FileFormat::Png,
+            bounds: Some(bounds),
+            center: Some((center, 3)),
+            ..Default::default()
+        };
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        tr.commit().unwrap();
+
+        let read = read_metadata(&conn).unwrap();
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+
+        let (written_bounds, read_bounds) = (metadata.bounds.unwrap(), read.bounds.unwrap());
+        assert!(close(written_bounds.top_left().lon(), read_bounds.top_left().lon()));
+        assert!(close(written_bounds.top_left().lat(), read_bounds.top_left().lat()));
+        assert!(close(
+            written_bounds.bottom_right().lon(),
+            read_bounds.bottom_right().lon()
+        ));
+        assert!(close(
+            written_bounds.bottom_right().lat(),
+            read_bounds.bottom_right().lat()
+        ));
+
+        let ((written_center, _), (read_center, zoom)) = (metadata.center.unwrap(), read.center.unwrap());
+        assert!(close(written_center.lon(), read_center.lon()));
+        assert!(close(written_center.lat(), read_center.lat()));
+        assert_eq!(zoom, 3);
+    }
+
+    #[test]
+    fn apply_patch_to_base() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+
+        let mut base = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = base.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(0, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![1]).unwrap();
+        tr.commit().unwrap();
+
+        let mut patch = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = patch.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![2]).unwrap();
+        write_tile(&tr, tile_id(2, 0, 0), vec![3]).unwrap();
+        tr.commit().unwrap();
+
+        let tr = base.transaction().unwrap();
+        assert_eq!(apply_patch(&tr, &patch, &[tile_id(0, 0, 0)]).unwrap(), (2, 1));
+        tr.commit().unwrap();
+
+        let tiles: Vec<_> = tiles(&base).map(|tile| tile.unwrap().1).collect();
+        assert_eq!(tiles, vec![vec![2], vec![3]]);
+        assert_eq!(read_metadata(&base).unwrap().zoom_range(), Some(1..=2));
+    }
+
+    #[test]
+    fn read_tiles_in_given_order() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(0, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![1]).unwrap();
+        tr.commit().unwrap();
+
+        let tile_ids = [tile_id(1, 0, 0), tile_id(2, 0, 0), tile_id(0, 0, 0), tile_id(1, 0, 0)];
+        let tiles: Vec<_> = read_tiles(&conn, &tile_ids)
+            .unwrap()
+            .into_iter()
+            .map(|(tile_id, tile_data)| ((tile_id.z(), tile_id.x(), tile_id.y()), tile_data))
+            .collect();
+        assert_eq!(
+            tiles,
+            vec![
+                ((1, 0, 0), Some(vec![1])),
+                ((2, 0, 0), None),
+                ((0, 0, 0), Some(vec![0])),
+                ((1, 0, 0), Some(vec![1])),
+            ]
+        );
+
+        // Spans several queries, with duplicates in each of them
+        let tile_ids: Vec<_> = (0..600).map(|i| tile_id(i % 3, 0, 0)).collect();
+        let tiles = read_tiles(&conn, &tile_ids).unwrap();
+        assert_eq!(tiles.len(), 600);
+        for (i, (_, tile_data)) in tiles.into_iter().enumerate() {
+            let expected = if i % 3 == 2 { None } else { Some(vec![(i % 3) as u8]) };
+            assert_eq!(tile_data, expected);
+        }
+    }
+
+    #[test]
+    fn clear_all_tiles() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+        let count = |tr: &Transaction, table: &str| -> i64 {
+            tr.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(0, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![1]).unwrap();
+        assert_eq!(clear_tiles(&tr).unwrap(), 2);
+        assert_eq!(count(&tr, "tiles"), 0);
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(0, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 1, 0), vec![1]).unwrap();
+        deduplicate(&tr).unwrap();
+        set_empty_tile(&tr, vec![2]).unwrap();
+        write_empty_tile_ref(&tr, tile_id(1, 1, 1)).unwrap();
+
+        assert_eq!(clear_tiles(&tr).unwrap(), 4);
+        assert_eq!(count(&tr, "map"), 0);
+        assert_eq!(count(&tr, "tiles"), 0);
+        let image_ids: Vec<String> = tr
+            .prepare("SELECT tile_id FROM images")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(image_ids, vec![EMPTY_TILE_ID]);
+    }
+
+    #[test]
+    fn deduplicate_tiles() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        write_tile(&tr, tile_id(1, 0, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 1, 0), vec![0]).unwrap();
+        write_tile(&tr, tile_id(1, 1, 1), vec![1, 2]).unwrap();
+
+        let stats = deduplicate(&tr).unwrap();
+        assert_eq!(stats.map_rows, 3);
+        assert_eq!(stats.image_rows, 2);
+        assert_eq!(stats.tile_bytes, 4);
+        assert_eq!(stats.image_bytes, 3);
+        assert_eq!(stats.saved_tiles(), 1);
+
+        let stats = deduplicate(&tr).unwrap();
+        assert_eq!((stats.map_rows, stats.image_rows), (3, 2));
+        assert_eq!((stats.tile_bytes, stats.image_bytes), (4, 3));
+
+        let tiles: Vec<_> = tiles(&tr).map(|tile| tile.unwrap().1).collect();
+        assert_eq!(tiles, vec![vec![0], vec![0], vec![1, 2]]);
+    }
+
+    #[test]
+    fn rebuild_index_keeps_last_duplicate() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        for &(z, x, y, data) in &[
+            (1, 0, 0, 0),
+            (1, 0, 0, 1),
+            (0, 0, 0, 2),
+            (1, 0, 0, 3),
+            (1, 1, 1, 4),
+            (1, 1, 1, 5),
+        ] {
+            write_tile(&tr, tile_id(z, x, y), vec![data]).unwrap();
+        }
+
+        let mut duplicates: Vec<_> = rebuild_tile_index(&tr)
+            .unwrap()
+            .into_iter()
+            .map(|tile_id| (tile_id.z(), tile_id.x(), tile_id.y()))
+            .collect();
+        duplicates.sort_unstable();
+        assert_eq!(duplicates, vec![(1, 0, 0), (1, 1, 1)]);
+
+        let tiles: Vec<_> = tiles(&tr).map(|tile| tile.unwrap().1).collect();
+        assert_eq!(tiles, vec![vec![2], vec![3], vec![5]]);
+
+        assert!(write_tile(&tr, tile_id(0, 0, 0), vec![6]).is_err());
+        assert!(rebuild_tile_index(&tr).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_missing_tiles() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+        let zoom = ZoomLevel::try_from(2).unwrap();
+        let coords = |tile_ids: Vec<TmsTileId>| -> Vec<_> {
+            tile_ids
+                .iter()
+                .map(|tile_id| (tile_id.z(), tile_id.x(), tile_id.y()))
+                .collect()
+        };
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        for &(x, y) in &[(0, 0), (1, 2)] {
+            write_tile(&tr, tile_id(2, x, y), vec![0]).unwrap();
+        }
+
+        // The range of the stored tiles without bounds
+        assert_eq!(
+            coords(missing_tiles(&tr, zoom).unwrap()),
+            vec![(2, 0, 1), (2, 0, 2), (2, 1, 0), (2, 1, 1)]
+        );
+        assert!(missing_tiles(&tr, ZoomLevel::try_from(3).unwrap()).unwrap().is_empty());
+
+        set_metadata_value(&tr, "bounds", "10,10,80,60").unwrap();
+        assert_eq!(coords(missing_tiles(&tr, zoom).unwrap()), vec![(2, 2, 2)]);
+        write_tile(&tr, tile_id(2, 2, 2), vec![0]).unwrap();
+        assert!(missing_tiles(&tr, zoom).unwrap().is_empty());
+
+        set_metadata_value(&tr, "bounds", "10,10").unwrap();
+        assert!(matches!(
+            missing_tiles(&tr, zoom),
+            Err(rusqlite::Error::FromSqlConversionFailure(..))
+        ));
+    }
+
+    #[test]
+    fn copy_empty_tile_refs() {
+        let tile_id = |z, x, y| tile_id_from_row(z, x, y).unwrap();
+        let stored_len = |conn: &Connection, (z, x, y): (u32, u32, u32)| -> i64 {
+            conn.query_row(
+                "SELECT LENGTH(tile_data) FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                params![z, x, y],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let create = |empty_tile: Option<Vec<u8>>| {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let tr = conn.transaction().unwrap();
+            create_metadata_table(&tr).unwrap();
+            create_tiles_table(&tr).unwrap();
+            if let Some(empty_tile) = empty_tile {
+                set_empty_tile(&tr, empty_tile).unwrap();
+            }
+            tr.commit().unwrap();
+            conn
+        };
+
+        let mut src = create(Some(vec![9, 9]));
+        let tr = src.transaction().unwrap();
+        set_metadata_value(&tr, "name", "test").unwrap();
+        write_empty_tile_ref(&tr, tile_id(1, 0, 0)).unwrap();
+        write_tile(&tr, tile_id(1, 1, 0), vec![1]).unwrap();
+        assert_eq!(transform_tiles(&tr, |_, tile_data| Some(tile_data)).unwrap(), (2, 0));
+        tr.commit().unwrap();
+        assert_eq!(stored_len(&src, (1, 0, 0)), 0);
+
+        let zoom = ZoomLevel::try_from(1).unwrap();
+        let mut dst = create(None);
+        let tr = dst.transaction().unwrap();
+        assert_eq!(extract_zoom_range(&src, &tr, zoom..=zoom).unwrap(), 2);
+        tr.commit().unwrap();
+        assert_eq!(stored_len(&dst, (1, 0, 0)), 0);
+        assert_eq!(read_tile(&dst, tile_id(1, 0, 0)).unwrap(), Some(vec![9, 9]));
+
+        let mut base = create(None);
+        let tr = base.transaction().unwrap();
+        apply_patch(&tr, &src, &[]).unwrap();
+        tr.commit().unwrap();
+        assert_eq!(stored_len(&base, (1, 0, 0)), 0);
+        assert_eq!(read_tile(&base, tile_id(1, 0, 0)).unwrap(), Some(vec![9, 9]));
+
+        // A different empty tile of the destination isn't replaced
+        let mut base = create(Some(vec![8]));
+        let tr = base.transaction().unwrap();
+        apply_patch(&tr, &src, &[]).unwrap();
+        tr.commit().unwrap();
+        assert_eq!(stored_len(&base, (1, 0, 0)), 2);
+        assert_eq!(read_tile(&base, tile_id(1, 0, 0)).unwrap(), Some(vec![9, 9]));
+    }
+
+    #[test]
+    fn empty_tile_refs() {
+        let tile_id = tile_id_from_row(1, 0, 0).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        set_empty_tile(&tr, vec![0]).unwrap();
+        write_empty_tile_ref(&tr, tile_id).unwrap();
+
+        assert_eq!(read_tile(&tr, tile_id).unwrap(), Some(vec![0]));
+        assert_eq!(read_tile_arc(&tr, tile_id).unwrap().as_deref(), Some(&[0][..]));
+        assert_eq!(TileQuery::new(&tr).unwrap().get(tile_id).unwrap(), Some(vec![0]));
+        assert_eq!(
+            MbtilesReader::with_schema(&tr, "main").tile(tile_id).unwrap(),
+            Some(vec![0])
+        );
+        assert_eq!(tiles(&tr).next().unwrap().unwrap().1, vec![0]);
+
+        // The reference is at the lowest zoom level
+        write_tile(&tr, tile_id_from_row(2, 0, 0).unwrap(), vec![2]).unwrap();
+        assert_eq!(read_overview_tile(&tr).unwrap().map(|tile| tile.1), Some(vec![0]));
+
+        let mut buf = Vec::new();
+        assert!(read_tile_into(&tr, tile_id, &mut buf).unwrap());
+        assert_eq!(buf, vec![0]);
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        deduplicate(&tr).unwrap();
+        set_empty_tile(&tr, vec![1]).unwrap();
+        write_empty_tile_ref(&tr, tile_id).unwrap();
+
+        assert_eq!(read_tile(&tr, tile_id).unwrap(), Some(vec![1]));
+
+        clear_tiles(&tr).unwrap();
+        write_empty_tile_ref(&tr, tile_id).unwrap();
+
+        assert_eq!(read_tile(&tr, tile_id).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn inferred_compression_not_written() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        set_metadata_value(&tr, "name", "test").unwrap();
+        write_tile(
+            &tr,
+            tile_id_from_row(0, 0, 0).unwrap(),
+            br#"{"type":"Feature"}"#.to_vec(),
+        )
+        .unwrap();
+
+        let metadata = read_metadata(&tr).unwrap();
+        assert_eq!(metadata.compression, None);
+        assert_eq!(metadata.inferred_compression, None);
+
+        tr.execute("DELETE FROM metadata", []).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        assert!(get_metadata_value(&tr, "compression").unwrap().is_none());
+
+        tr.execute("DELETE FROM tiles", []).unwrap();
+        write_tile(&tr, tile_id_from_row(0, 0, 0).unwrap(), vec![0x1f, 0x8b, 0x08, 0x00]).unwrap();
+
+        let metadata = read_metadata(&tr).unwrap();
+        assert_eq!(metadata.effective_compression(), Some(Compression::Gzip));
+
+        tr.execute("DELETE FROM metadata", []).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        assert!(get_metadata_value(&tr, "compression").unwrap().is_none());
+    }
+
+    #[test]
+    fn extract_zoom_levels() {
+        let mut src = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = src.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        create_tile_index(&tr).unwrap();
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            center: Some((GeoCoord::from_degrees(0.0, 0.0).unwrap(), 0)),
+            minzoom: Some(0),
+            maxzoom: Some(3),
+            ..Default::default()
+        };
+        write_metadata(&tr, &metadata).unwrap();
+        for &(z, x, y) in &[(0, 0, 0), (1, 0, 0), (2, 2, 1), (3, 0, 0)] {
+            write_tile(&tr, tile_id_from_row(z, x, y).unwrap(), vec![z as u8]).unwrap();
+        }
+        tr.commit().unwrap();
+
+        let zoom = |zoom: u32| ZoomLevel::try_from(zoom).unwrap();
+        let mut dst = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = dst.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        assert_eq!(extract_zoom_range(&src, &tr, zoom(1)..=zoom(2)).unwrap(), 2);
+
+        let zooms: Vec<u32> = tiles(&tr).map(|tile| tile.unwrap().0.z()).collect();
+        assert_eq!(zooms, vec![1, 2]);
+
+        let metadata = read_metadata(&tr).unwrap();
+        assert_eq!((metadata.minzoom, metadata.maxzoom), (Some(1), Some(2)));
+        assert_eq!(metadata.center.unwrap().1, 1);
+
+        let [west, south, east, north] = metadata.bounds_array().unwrap();
+        assert!((west - -180.0).abs() < 1e-6);
+        assert!(south < -85.05);
+        assert!((east - 90.0).abs() < 1e-6);
+        assert!(north.abs() < 1e-6);
+    }
+
+    #[test]
+    fn tile_writer_batches() {
+        let uri = "file:tile_writer_batches?mode=memory&cache=shared";
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_URI;
+
+        // Keeps the database alive, and makes inserts fail while `fail.enabled` is set
+        let mut control = Connection::open_with_flags(uri, flags).unwrap();
+        let tr = control.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        create_tiles_table(&tr).unwrap();
+        tr.execute_batch(
+            "CREATE TABLE fail (enabled INTEGER);
+            INSERT INTO fail VALUES (0);
+            CREATE TRIGGER fail_insert BEFORE INSERT ON tiles WHEN (SELECT enabled FROM fail)
+            BEGIN SELECT RAISE(ABORT, 'insert failed'); END;",
+        )
+        .unwrap();
+        tr.commit().unwrap();
+
+        let tile_id = |y| tile_id_from_row(2, 0, y).unwrap();
+        let mut writer = TileWriter::new(Connection::open_with_flags(uri, flags).unwrap(), 2);
+
+        writer.put(tile_id(0), vec![0]).unwrap();
+        assert_eq!(writer.written(), 0);
+        writer.put(tile_id(1), vec![1]).unwrap();
+        assert_eq!(writer.written(), 2);
+        writer.put(tile_id(2), vec![2]).unwrap();
+        assert_eq!(writer.written(), 2);
+
+        control.execute("UPDATE fail SET enabled = 1", []).unwrap();
+        assert!(writer.put(tile_id(3), vec![3]).is_err());
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.written(), 2);
+
+        control.execute("UPDATE fail SET enabled = 0", []).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.written(), 4);
+
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            ..Default::default()
+        };
+        let conn = writer.finish(&metadata).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM tiles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn write_outcomes() {
+        let tile_id = tile_id_from_row(1, 0, 0).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tiles_table(&tr).unwrap();
+        create_tile_index(&tr).unwrap();
+
+        assert_eq!(upsert_tile(&tr, tile_id, vec![0]).unwrap(), WriteOutcome::Inserted);
+        assert_eq!(upsert_tile(&tr, tile_id, vec![1]).unwrap(), WriteOutcome::Replaced);
+        assert_eq!(
+            write_tile_or_ignore(&tr, tile_id, vec![2]).unwrap(),
+            WriteOutcome::Ignored
+        );
+
+        assert_eq!(read_tile(&tr, tile_id).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn lone_minzoom_round_trip() {
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            minzoom: Some(3),
+            ..Default::default()
+        };
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        tr.commit().unwrap();
+
+        let read = read_metadata(&conn).unwrap();
+        assert_eq!(read.minzoom, Some(3));
+        assert_eq!(read.maxzoom, None);
+        assert_eq!(read.zoom_range(), None);
+    }
+
+    #[test]
+    fn custom_json_round_trip() {
+        let mut metadata = Metadata {
+            name: "test".to_owned(),
+            ..Default::default()
+        };
+        metadata.custom.insert("a".to_owned(), "1".to_owned());
+        metadata.custom.insert("b".to_owned(), "2".to_owned());
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        write_metadata_with_custom_json(&tr, &metadata).unwrap();
+        set_metadata_value(&tr, "b", "3").unwrap();
+        tr.commit().unwrap();
+
+        let read = read_metadata(&conn).unwrap();
+        assert_eq!(read.custom.len(), 2);
+        assert_eq!(read.custom["a"], "1");
+        assert_eq!(read.custom["b"], "3");
+    }
+
+    #[test]
+    fn malformed_custom_json_kept() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        set_metadata_value(&tr, "name", "test").unwrap();
+        set_metadata_value(&tr, "custom_json", "[1, 2]").unwrap();
+        tr.commit().unwrap();
+
+        let read = read_metadata(&conn).unwrap();
+        assert_eq!(read.custom.len(), 1);
+        assert_eq!(read.custom["custom_json"], "[1, 2]");
+    }
+
+    #[test]
+    fn other_format_json_round_trip() {
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            format: FileFormat::other("application/vnd.example").unwrap(),
+            raw_json: Some(r#"{"a": 1}"#.to_owned()),
+            ..Default::default()
+        };
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        tr.commit().unwrap();
+
+        assert_eq!(read_metadata(&conn).unwrap().raw_json, metadata.raw_json);
+    }
+
+    #[test]
+    fn reject_degenerate_bounds() {
+        let corner = GeoCoord::from_degrees(10.0, 10.0).unwrap();
+        let metadata = Metadata {
+            name: "test".to_owned(),
+            bounds: GeoRect::new(corner.clone(), corner).ok(),
+            ..Default::default()
+        };
+        assert!(metadata.bounds.is_some());
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        assert!(write_metadata(&tr, &metadata).is_err());
+        tr.commit().unwrap();
+
+        assert!(read_metadata_raw(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn antimeridian_bounds_round_trip() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_metadata_table(&tr).unwrap();
+        tr.execute(
+            "INSERT INTO metadata (name, value) VALUES ('bounds', '177,-20,-178,-12')",
+            [],
+        )
+        .unwrap();
+
+        let metadata = read_metadata(&tr).unwrap();
+        assert!(metadata.bounds.is_none());
+        assert!(metadata.antimeridian_bounds.is_some());
+
+        tr.execute("DELETE FROM metadata", []).unwrap();
+        write_metadata(&tr, &metadata).unwrap();
+        assert_eq!(
+            crate::read::get_metadata_value(&tr, "bounds").unwrap().as_deref(),
+            Some("177,-20,-178,-12")
+        );
+    }
 
     #[test]
     fn write_vector_layer() {